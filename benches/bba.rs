@@ -0,0 +1,430 @@
+use algebra::{
+    pasta::{
+        fp::Fp,
+        fq::Fq,
+        pallas::{Affine as Other, PallasParameters},
+        vesta::{Affine, VestaParameters},
+    },
+    AffineCurve, ProjectiveCurve, UniformRand,
+};
+use array_init::array_init;
+use brave_poc::{
+    bba, bba_delta_proof, bba_init_proof, bba_open_proof, bba_update_proof, fft, metrics,
+    proof_system::*,
+    schnorr::{self, CoordinateCurve, SignatureParams, Signer},
+    transparency_log,
+};
+use commitment_dlog::{
+    commitment::{ceil_log2, CommitmentCurve, PolyComm},
+    srs::{endos, SRS},
+};
+use criterion::{black_box, Criterion};
+use groupmap::GroupMap;
+use oracle::{
+    poseidon_5_wires::*,
+    sponge_5_wires::{DefaultFqSponge, DefaultFrSponge},
+};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+type SpongeQ = DefaultFqSponge<VestaParameters, PlonkSpongeConstants>;
+type SpongeR = DefaultFrSponge<Fp, PlonkSpongeConstants>;
+
+type PSpongeQ = DefaultFqSponge<PallasParameters, PlonkSpongeConstants>;
+type PSpongeR = DefaultFrSponge<Fq, PlonkSpongeConstants>;
+
+// Criterion keeps its own reports under target/criterion. Next to them every
+// benchmark's timings are collected here and written to a single JSON file, so
+// that runs on different commits can be diffed directly.
+struct Report {
+    // The number of samples Criterion measures per benchmark
+    sample_size: usize,
+    // name -> (parameters, runs of (iterations, elapsed))
+    results: RefCell<BTreeMap<String, (Vec<(&'static str, usize)>, Vec<(u64, Duration)>)>>,
+}
+
+impl Report {
+    fn record(&self, id: &str, params: &[(&'static str, usize)], iters: u64, elapsed: Duration) {
+        let mut results = self.results.borrow_mut();
+        let entry = results
+            .entry(id.to_string())
+            .or_insert_with(|| (params.to_vec(), vec![]));
+        entry.1.push((iters, elapsed));
+    }
+
+    fn json(&self) -> String {
+        let mut out = String::from("[\n");
+        let results = self.results.borrow();
+        for (i, (id, (params, runs))) in results.iter().enumerate() {
+            // Criterion calls the routine during warm-up too, and measures its
+            // samples last, so only the last [sample_size] runs are samples
+            let samples = &runs[runs.len().saturating_sub(self.sample_size)..];
+            let iters: u64 = samples.iter().map(|(n, _)| n).sum();
+            // Like Criterion's estimates, these are over the per-iteration time of
+            // each sample
+            let mut per_iter: Vec<f64> = samples
+                .iter()
+                .map(|(n, d)| d.as_nanos() as f64 / *n as f64)
+                .collect();
+            per_iter.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            let mean = per_iter.iter().sum::<f64>() / per_iter.len() as f64;
+            let median = per_iter[per_iter.len() / 2];
+            let params: Vec<_> = params
+                .iter()
+                .map(|(k, v)| format!("\"{}\":{}", k, v))
+                .collect();
+            write!(
+                out,
+                "  {{\"id\":\"{}\",\"params\":{{{}}},\"samples\":{},\"iterations\":{},\"mean_ns\":{:.0},\"median_ns\":{:.0},\"min_ns\":{:.0}}}",
+                id,
+                params.join(","),
+                samples.len(),
+                iters,
+                mean,
+                median,
+                per_iter[0]
+            )
+            .unwrap();
+            out.push_str(if i + 1 < results.len() { ",\n" } else { "\n" });
+        }
+        out.push_str("]\n");
+        out
+    }
+}
+
+// Benchmarks [routine] on a fresh input from [setup] each iteration, timing only
+// the routine
+fn bench<I, O>(
+    c: &mut Criterion,
+    report: &Report,
+    name: &str,
+    params: &[(&'static str, usize)],
+    mut setup: impl FnMut() -> I,
+    mut routine: impl FnMut(I) -> O,
+) {
+    let param_string: Vec<_> = params.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+    let id = if params.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", name, param_string.join(","))
+    };
+    c.bench_function(&id, |b| {
+        b.iter_custom(|iters| {
+            let mut elapsed = Duration::from_secs(0);
+            for _ in 0..iters {
+                let input = setup();
+                let start = Instant::now();
+                black_box(routine(input));
+                elapsed += start.elapsed();
+            }
+            report.record(&id, params, iters, elapsed);
+            elapsed
+        })
+    });
+}
+
+// A comma-separated list of sizes from the environment variable [var], or [default]
+fn sweep(var: &str, default: &[usize]) -> Vec<usize> {
+    match std::env::var(var) {
+        Ok(s) => s.split(',').map(|x| x.trim().parse().unwrap()).collect(),
+        Err(_) => default.to_vec(),
+    }
+}
+
+// The value of `--sample-size`, which Criterion does not expose once configured
+fn sample_size_arg() -> Option<usize> {
+    let args: Vec<String> = std::env::args().collect();
+    args.iter().enumerate().find_map(|(i, a)| {
+        if a == "--sample-size" {
+            args.get(i + 1)?.parse().ok()
+        } else if a.starts_with("--sample-size=") {
+            a["--sample-size=".len()..].parse().ok()
+        } else {
+            None
+        }
+    })
+}
+
+fn main() {
+    // Set after configure_from_args, so that Criterion measures exactly as many
+    // samples as the report takes
+    let sample_size = sample_size_arg().unwrap_or(10);
+    let mut c = Criterion::default().configure_from_args().sample_size(sample_size);
+    let report = Report {
+        sample_size,
+        results: RefCell::new(BTreeMap::new()),
+    };
+
+    let batch_sizes = sweep("BBA_BENCH_BATCH_SIZES", &[1, 10, 100]);
+    let updates_per_request = sweep("BBA_BENCH_UPDATES", &[1, 10, 100]);
+    let domain_sizes = sweep("BBA_BENCH_DOMAIN_SIZES", &[1 << 10, 1 << 11, 1 << 12]);
+
+    let (_endo_q, endo_r) = endos::<Other>();
+    let signer = Signer::<Other> {
+        sponge: oracle::pasta::fp5::params(),
+        endo: endo_r,
+    };
+
+    let brave_sk = <Other as AffineCurve>::ScalarField::rand(&mut rand_core::OsRng);
+    let brave_pubkey = Other::prime_subgroup_generator()
+        .mul(brave_sk)
+        .into_affine();
+
+    {
+        let m = schnorr::point_message(&brave_pubkey);
+        let s = signer.sign(brave_sk, m.clone());
+        bench(&mut c, &report, "schnorr_sign", &[], || m.clone(), |m| {
+            signer.sign(brave_sk, m)
+        });
+        bench(&mut c, &report, "schnorr_verify", &[], || m.clone(), |m| {
+            assert!(signer.verify(brave_pubkey, m, s))
+        });
+    }
+
+    for &n in domain_sizes.iter() {
+        let srs = SRS::<Affine>::create(n);
+        bench(&mut c, &report, "lagrange_commitments", &[("domain_size", n)], || (), |_| {
+            fft::lagrange_commitments(&srs)
+        });
+    }
+
+    // The same setup as main.rs
+    let other_srs = SRS::<Other>::create(1 << ceil_log2(bba::MAX_COUNTERS));
+    let srs = SRS::<Affine>::create(1 << 11);
+    let big_srs = SRS::<Affine>::create(1 << 12);
+    let group_map = <Affine as CommitmentCurve>::Map::setup();
+    let g_group_map = <Other as CommitmentCurve>::Map::setup();
+
+    let proof_system_constants = fp_constants();
+    let fq_proof_system_constants = fq_constants();
+
+    let bba = bba::Params::new(&other_srs, endo_r);
+
+    let init_params = bba_init_proof::Params {
+        lagrange_commitments: array_init(|i| bba.lagrange_commitments[i]),
+        h: other_srs.h,
+    };
+    let update_params = bba_update_proof::Params {
+        brave_pubkey: brave_pubkey.to_coordinates().unwrap(),
+        h: other_srs.h.to_coordinates().unwrap(),
+    };
+    let open_params = bba_open_proof::Params::new(
+        vec![(0..bba::MAX_COUNTERS)
+            .map(|i| {
+                let i = i as u32;
+                i * i + 1
+            })
+            .collect()],
+        vec![],
+    );
+
+    let fq_poseidon = oracle::pasta::fq5::params();
+    let fp_poseidon = oracle::pasta::fp5::params();
+
+    let init_pk = generate_proving_key::<FpInner, _>(
+        &big_srs,
+        &proof_system_constants,
+        &fq_poseidon,
+        2,
+        |sys, p| {
+            bba_init_proof::circuit::<_, Other, _>(
+                &proof_system_constants,
+                &init_params,
+                &None,
+                sys,
+                p,
+            )
+        },
+    );
+    let init_vk = init_pk.verifier_index();
+    let update_pk = generate_proving_key::<FpInner, _>(
+        &srs,
+        &proof_system_constants,
+        &fq_poseidon,
+        2,
+        |sys, p| {
+            bba_update_proof::circuit::<_, Other, _>(
+                &proof_system_constants,
+                &update_params,
+                &None,
+                sys,
+                p,
+            )
+        },
+    );
+    let update_vk = update_pk.verifier_index();
+    let open_pk = generate_proving_key::<FqInner, _>(
+        &other_srs,
+        &fq_proof_system_constants,
+        &fp_poseidon,
+        2,
+        |sys, p| bba_open_proof::circuit::<_, Affine, _>(&open_params, &None, sys, p),
+    );
+    let open_vk = open_pk.verifier_index();
+    let delta_pk = generate_proving_key::<FqInner, _>(
+        &other_srs,
+        &fq_proof_system_constants,
+        &fp_poseidon,
+        bba_delta_proof::PUBLIC_INPUT,
+        |sys, p| bba_delta_proof::circuit(&None, sys, p),
+    );
+
+    let poly_comms = |srs: &SRS<Affine>| -> Vec<PolyComm<Affine>> {
+        fft::lagrange_commitments(srs)
+            .iter()
+            .map(|g| PolyComm {
+                unshifted: vec![*g],
+                shifted: None,
+            })
+            .collect()
+    };
+
+    let update_authority = bba::UpdateAuthority {
+        signing_key: brave_sk,
+        signer: signer.clone(),
+        group_map: group_map.clone(),
+        init_vk,
+        other_lgr_comms: poly_comms(&srs),
+        big_other_lgr_comms: poly_comms(&big_srs),
+        lgr_comms: bba.lagrange_commitments.clone(),
+        update_vk,
+        log: std::sync::Mutex::new(transparency_log::Log::new(signer.sponge.clone())),
+        metrics: metrics::Metrics::new(false),
+    };
+
+    let user_config = bba::UserConfig {
+        signer: signer.clone(),
+        bba: bba.clone(),
+        authority_public_key: brave_pubkey,
+        prover: bba::UserProver {
+            group_map: group_map.clone(),
+            g_group_map: g_group_map.clone(),
+            open_pk,
+            delta_pk,
+            open_params: open_params.clone(),
+            proof_system_constants: proof_system_constants.clone(),
+            init_params: init_params.clone(),
+            update_params: update_params.clone(),
+            init_pk,
+            update_pk,
+        },
+    };
+
+    let init_secrets = bba::init_secrets();
+    bench(&mut c, &report, "request_init", &[], || (), |_| {
+        user_config.request_init::<SpongeQ, SpongeR>(init_secrets)
+    });
+
+    let init_request = user_config.request_init::<SpongeQ, SpongeR>(init_secrets);
+    for &n in batch_sizes.iter() {
+        bench(
+            &mut c,
+            &report,
+            "batch_init",
+            &[("batch_size", n)],
+            || vec![init_request.clone(); n],
+            |reqs| {
+                update_authority
+                    .batch_init::<SpongeQ, SpongeR>(reqs)
+                    .unwrap()
+            },
+        );
+    }
+
+    let init_signature = update_authority
+        .batch_init::<SpongeQ, SpongeR>(vec![init_request])
+        .unwrap()[0];
+    let mut user = bba::User::<FpInner>::init(user_config, init_secrets, init_signature).unwrap();
+
+    let updates = |k: usize| -> Vec<_> {
+        (0..k)
+            .map(|i| bba::SingleUpdate {
+                campaign_index: i as u32,
+                delta: 1,
+            })
+            .collect()
+    };
+
+    for &k in updates_per_request.iter() {
+        // Each request is remembered as pending, so the previous one is abandoned
+        // before the next is made
+        let user = RefCell::new(&mut user);
+        let pending = RefCell::new(None);
+        bench(
+            &mut c,
+            &report,
+            "request_update",
+            &[("updates", k)],
+            || {
+                if let Some(acc) = pending.borrow_mut().take() {
+                    assert!(user.borrow_mut().abandon_update(&acc));
+                }
+                updates(k)
+            },
+            |u| {
+                let req = user.borrow_mut().request_update::<SpongeQ, SpongeR>(u).unwrap();
+                *pending.borrow_mut() = Some(req.randomized_acc());
+                req
+            },
+        );
+        if let Some(acc) = pending.into_inner() {
+            user.borrow_mut().abandon_update(&acc);
+        }
+    }
+
+    for &k in updates_per_request.iter() {
+        let update_request = user.request_update::<SpongeQ, SpongeR>(updates(k)).unwrap();
+        for &n in batch_sizes.iter() {
+            bench(
+                &mut c,
+                &report,
+                "perform_updates",
+                &[("batch_size", n), ("updates", k)],
+                || vec![update_request.clone(); n],
+                |reqs| update_authority.perform_updates::<SpongeQ, SpongeR>(reqs),
+            );
+        }
+    }
+
+    let update_request = user.request_update::<SpongeQ, SpongeR>(updates(1)).unwrap();
+    let resp = update_authority.perform_updates::<SpongeQ, SpongeR>(vec![update_request])[0]
+        .as_ref()
+        .unwrap()
+        .clone();
+    user.process_update_response(&resp).unwrap();
+
+    bench(&mut c, &report, "open", &[], || (), |_| {
+        user.open::<PSpongeQ, PSpongeR>().unwrap()
+    });
+
+    let opening = user.open::<PSpongeQ, PSpongeR>().unwrap();
+    for &n in batch_sizes.iter() {
+        bench(
+            &mut c,
+            &report,
+            "verify_batch",
+            &[("batch_size", n)],
+            || vec![&opening; n],
+            |openings| {
+                bba::RewardOpening::verify_batch::<PSpongeQ, PSpongeR>(
+                    &signer,
+                    &bba,
+                    brave_pubkey,
+                    &g_group_map,
+                    &open_vk,
+                    openings,
+                )
+                .unwrap()
+            },
+        );
+    }
+
+    c.final_summary();
+
+    // Set BBA_BENCH_JSON to choose where the JSON summary goes
+    let path = std::env::var("BBA_BENCH_JSON").unwrap_or_else(|_| "target/bba-bench.json".into());
+    std::fs::write(&path, report.json()).unwrap();
+    println!("Wrote benchmark summary to {}", path);
+}
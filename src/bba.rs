@@ -1,11 +1,15 @@
 use rayon::prelude::*;
+use crate::bba_delta_proof;
 use crate::bba_init_proof;
 use crate::bba_open_proof;
 use crate::bba_update_proof;
 use crate::endo::EndoScalar;
 use crate::fft::lagrange_commitments;
+use crate::metrics::Metrics;
 use crate::proof_system;
 use crate::schnorr;
+use crate::transparency_log::{self, Entry, SignedTreeHead};
+use crate::util::{field_from_hex, field_hex};
 use algebra::{AffineCurve, PrimeField, ProjectiveCurve, UniformRand, VariableBaseMSM, Zero};
 use array_init::array_init;
 use commitment_dlog::{
@@ -19,6 +23,8 @@ use plonk_5_wires_protocol_dlog::{
     prover::ProverProof,
 };
 use schnorr::SignatureParams;
+use std::sync::Mutex;
+use std::time::Instant;
 
 #[derive(Clone)]
 pub struct Params<G: AffineCurve> {
@@ -57,7 +63,10 @@ impl<G: AffineCurve> Params<G> {
             secrets.alpha[3],
             secrets.alpha[4],
         ];
-        let scalars: Vec<_> = scalars.iter().map(|x| x.into_repr()).collect();
+        let scalars: Vec<_> = scalars
+            .iter()
+            .map(|x| x.to_field(&self.endo).into_repr())
+            .collect();
         VariableBaseMSM::multi_scalar_mul(bases.as_slice(), scalars.as_slice()).into_affine()
     }
 }
@@ -88,6 +97,23 @@ pub struct UpdateRequest<G: AffineCurve, Other: AffineCurve> {
     randomized_acc: G,
 }
 
+impl<G: AffineCurve, Other: AffineCurve> UpdateRequest<G, Other> {
+    // Identifies the request to User::abandon_update
+    pub fn randomized_acc(&self) -> G {
+        self.randomized_acc
+    }
+}
+
+// An update request which does not reveal the counters it adds to. [update] has no
+// updates of its own; instead the authority adds the commitment to column 0 of
+// [delta_proof], which proves each of its deltas to be 0 or 1 (see
+// bba_delta_proof).
+#[derive(Clone)]
+pub struct PrivateUpdateRequest<G: AffineCurve, Other: AffineCurve> {
+    pub update: UpdateRequest<G, Other>,
+    pub delta_proof: ProverProof<G>,
+}
+
 // size in bytes
 pub fn proof_size<G: CommitmentCurve>(proof: &ProverProof<G>) -> usize {
     fn poly_comm<A>(pc: &PolyComm<A>) -> usize {
@@ -130,9 +156,17 @@ pub fn proof_size<G: CommitmentCurve>(proof: &ProverProof<G>) -> usize {
 // that themselves.
 #[derive(Clone)]
 pub struct UpdateResponse<G: AffineCurve> {
+    // The accumulator of the request, so the user can match the response to it
+    randomized_acc: G,
     signature: schnorr::Signature<G>,
 }
 
+impl<G: AffineCurve> UpdateResponse<G> {
+    pub fn randomized_acc(&self) -> G {
+        self.randomized_acc
+    }
+}
+
 pub struct UpdateAuthority<'a, G: schnorr::CoordinateCurve, Other: CommitmentCurve> {
     pub signing_key: schnorr::PrivateKey<G>,
     pub signer: schnorr::Signer<G>,
@@ -142,6 +176,9 @@ pub struct UpdateAuthority<'a, G: schnorr::CoordinateCurve, Other: CommitmentCur
     pub other_lgr_comms: Vec<PolyComm<Other>>,
     pub big_other_lgr_comms: Vec<PolyComm<Other>>,
     pub group_map: Other::Map,
+    // Every signed accumulator and redeemed nullifier, see [signed_tree_head]
+    pub log: Mutex<transparency_log::Log<G>>,
+    pub metrics: Metrics,
 }
 
 pub struct UserProver<'a, G: CommitmentCurve, Other: CommitmentCurve> {
@@ -151,6 +188,7 @@ pub struct UserProver<'a, G: CommitmentCurve, Other: CommitmentCurve> {
     pub init_pk: Index<'a, Other>,
     pub update_pk: Index<'a, Other>,
     pub open_pk: Index<'a, G>,
+    pub delta_pk: Index<'a, G>,
     pub update_params: bba_update_proof::Params<Other::ScalarField>,
     pub init_params: bba_init_proof::Params<G>,
     pub open_params: bba_open_proof::Params,
@@ -172,7 +210,56 @@ pub struct UserState<G: CommitmentCurve> {
     pub counters: Vec<u32>,
     pub acc: G,
     pub signature: schnorr::Signature<G>,
-    pub pending_update_witness: Option<Randomized<G>>,
+    // Every request re-randomizes [acc], so concurrent requests fork the accumulator
+    // chain: once one of their responses is processed, the others are for a
+    // superseded accumulator and get rejected, and their updates are lost. Their
+    // entries stay here until their response is processed or they are abandoned
+    // with [User::abandon_update]. Abandoning never touches [acc], whose signature
+    // stays valid, so a lost response only costs the updates of its request.
+    pub pending_updates: Vec<PendingUpdate<G>>,
+    // Set by [User::open]. Until the authority acknowledges the redemption the
+    // accumulator is still usable, and [User::restore] clears the marker.
+    pub redemption: Option<Redemption<G::ScalarField>>,
+}
+
+// The redemption state of an accumulator, with its nullifier
+#[derive(Clone, Debug, PartialEq)]
+pub enum Redemption<F> {
+    // Also records the rewards the opening discloses and when it was made, in
+    // seconds since the Unix epoch, to check the authority's receipt against
+    Opened {
+        nullifier: F,
+        amounts: Vec<u64>,
+        opened_at: u64,
+    },
+    Redeemed(F),
+}
+
+// How far the authority's clock may be behind the user's when it timestamps a
+// receipt, in seconds
+const RECEIPT_CLOCK_SKEW: u64 = 300;
+
+fn unix_time() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+pub struct PendingUpdate<G: AffineCurve> {
+    // The accumulator the request re-randomized
+    pub base: G,
+    pub randomized: Randomized<G>,
+    pub updates: Vec<SingleUpdate>,
+    // For a private update, the commitment the authority adds in place of [updates]
+    pub delta: Option<PrivateDelta<G>>,
+}
+
+// The commitment of a private update, with the blinding it adds to the accumulator
+pub struct PrivateDelta<G: AffineCurve> {
+    pub commitment: G,
+    pub r: G::ScalarField,
+    pub alpha: [G::ScalarField; proof_system::ZK_ROWS],
 }
 
 pub struct User<'a, C: proof_system::Cycle> {
@@ -187,8 +274,86 @@ pub struct RewardOpening<C: proof_system::Cycle> {
 }
 
 pub struct Payout<C: proof_system::Cycle> {
-    pub amount: u64,
+    // The reward under each price schedule of bba_open_proof::Params
+    pub amounts: Vec<u64>,
     pub nullifier: C::OuterField,
+    // The counter sum of each category of the opening proof's disclosure policy,
+    // see bba_open_proof::Params
+    pub category_sums: Vec<u64>,
+}
+
+// Signed by the authority when it pays out an opening, so the user can prove the
+// payment in a dispute
+#[derive(Clone, Debug, PartialEq)]
+pub struct RedemptionReceipt<G: AffineCurve> {
+    pub nullifier: G::ScalarField,
+    pub amounts: Vec<u64>,
+    // Seconds since the Unix epoch
+    pub timestamp: u64,
+    pub signature: schnorr::Signature<G>,
+}
+
+// Receipts are signed with the accumulator signing key. Their messages start with
+// this tag and are longer than the point messages signed for accumulators, so a
+// receipt signature never verifies as an accumulator signature.
+const RECEIPT_TAG: u64 = u64::from_le_bytes(*b"bbarcpt0");
+
+fn receipt_message<G: schnorr::CoordinateCurve>(
+    nullifier: G::ScalarField,
+    amounts: &[u64],
+    timestamp: u64,
+) -> Vec<G::BaseField>
+where
+    G::BaseField: PrimeField,
+{
+    let [lo, hi] = schnorr::scalar_halves::<G>(nullifier);
+    let mut m = vec![G::BaseField::from(RECEIPT_TAG), lo, hi, G::BaseField::from(timestamp)];
+    m.extend(amounts.iter().map(|a| G::BaseField::from(*a)));
+    m
+}
+
+impl<G: schnorr::CoordinateCurve> RedemptionReceipt<G>
+where
+    G::BaseField: PrimeField,
+{
+    pub fn verify(&self, signer: &schnorr::Signer<G>, authority_public_key: G) -> bool {
+        let m = receipt_message::<G>(self.nullifier, &self.amounts, self.timestamp);
+        signer.verify(authority_public_key, m, self.signature)
+    }
+
+    // The line recording this receipt in the authority's receipt log:
+    //   timestamp nullifier amount,..,amount r s
+    // with the field elements in hex as written by util::field_hex
+    pub fn log_line(&self) -> String {
+        let amounts: Vec<_> = self.amounts.iter().map(|a| a.to_string()).collect();
+        format!(
+            "{} {} {} {} {}",
+            self.timestamp,
+            field_hex(&self.nullifier),
+            amounts.join(","),
+            field_hex(&self.signature.0),
+            field_hex(&self.signature.1)
+        )
+    }
+
+    pub fn from_log_line(line: &str) -> Option<Self> {
+        // Split on single spaces, as an empty list of amounts is an empty field
+        let fields: Vec<_> = line.split(' ').collect();
+        if fields.len() != 5 {
+            return None;
+        }
+        let amounts: Option<Vec<u64>> = if fields[2].is_empty() {
+            Some(vec![])
+        } else {
+            fields[2].split(',').map(|a| a.parse().ok()).collect()
+        };
+        Some(RedemptionReceipt {
+            timestamp: fields[0].parse().ok()?,
+            nullifier: field_from_hex(fields[1])?,
+            amounts: amounts?,
+            signature: (field_from_hex(fields[3])?, field_from_hex(fields[4])?),
+        })
+    }
 }
 
 impl<C: proof_system::Cycle> RewardOpening<C> {
@@ -224,10 +389,11 @@ impl<C: proof_system::Cycle> RewardOpening<C> {
         for opening in openings.iter() {
             let amount = opening.proof.public[1];
 
-            let acc = opening.proof.commitments.w_comm[0].unshifted[0].into_projective()
-                - &bba.lagrange_commitments[1].mul(amount);
+            let acc: C::Inner = (opening.proof.commitments.w_comm[0].unshifted[0].into_projective()
+                - &bba.lagrange_commitments[1].mul(amount))
+                .into();
 
-            if !signer.verify(authority_public_key, acc.into(), opening.signature) {
+            if !signer.verify(authority_public_key, schnorr::point_message(&acc), opening.signature) {
                 return Err(String::from("Open signature failed to verify"));
             }
         }
@@ -246,6 +412,7 @@ impl<C: proof_system::Cycle> RewardOpening<C> {
         authority_public_key: C::Inner,
         group_map: &C::InnerMap,
         vk: &VerifierIndex<'a, C::Inner>,
+        open_params: &bba_open_proof::Params,
     ) -> Result<Payout<C>, &str> {
         let lgr_comms: Vec<PolyComm<_>> = bba
             .lagrange_commitments
@@ -265,23 +432,29 @@ impl<C: proof_system::Cycle> RewardOpening<C> {
 
         let amount = self.proof.public[1];
 
-        let acc = self.proof.commitments.w_comm[0].unshifted[0].into_projective()
-            - &bba.lagrange_commitments[1].mul(amount);
+        let acc: C::Inner = (self.proof.commitments.w_comm[0].unshifted[0].into_projective()
+            - &bba.lagrange_commitments[1].mul(amount))
+            .into();
 
-        if !signer.verify(authority_public_key, acc.into(), self.signature) {
+        if !signer.verify(authority_public_key, schnorr::point_message(&acc), self.signature) {
             return Err("Open signature failed to verify");
         }
 
-        let nullifier = self.proof.public[0];
-        let amount = amount.into_repr();
-        let a = amount.as_ref();
-
-        for i in 1..a.len() {
-            assert_eq!(a[i], 0)
-        }
-        let amount = a[0];
+        self.payout(open_params)
+    }
 
-        Ok(Payout { amount, nullifier })
+    // The payout an opening claims. Only meaningful once the opening verifies.
+    pub fn payout(&self, open_params: &bba_open_proof::Params) -> Result<Payout<C>, &'static str> {
+        let nullifier = self.proof.public[0];
+        let (amounts, category_sums) = open_params
+            .unpack_total_value(self.proof.public[1])
+            .ok_or("Open total value is out of range")?;
+
+        Ok(Payout {
+            amounts,
+            nullifier,
+            category_sums,
+        })
     }
 }
 
@@ -302,48 +475,45 @@ fn update_delta<G: AffineCurve>(
     VariableBaseMSM::multi_scalar_mul(bases.as_slice(), scalars.as_slice())
 }
 
+// The counters after applying [updates], or None if one would overflow a u32
+fn apply_updates(counters: &[u32], updates: &[SingleUpdate]) -> Option<Vec<u32>> {
+    let mut res = counters.to_vec();
+    for u in updates {
+        let x = res.get_mut(u.campaign_index as usize)?;
+        *x = x.checked_add(u.delta)?;
+    }
+    Some(res)
+}
+
 pub fn init_secrets<G: AffineCurve>() -> bba_init_proof::Witness<G> {
     let rng = &mut rand_core::OsRng;
     bba_init_proof::Witness {
-        r: G::ScalarField::rand(rng),
-        c: G::ScalarField::rand(rng),
-        alpha: array_init(|_| G::ScalarField::rand(rng)),
+        r: EndoScalar(G::ScalarField::rand(rng)),
+        c: EndoScalar(G::ScalarField::rand(rng)),
+        alpha: array_init(|_| EndoScalar(G::ScalarField::rand(rng))),
     }
 }
 
 impl<'a, C: proof_system::Cycle> User<'a, C> {
+    // Checks that [acc] commits to [state] in the layout of column 0 of the opening
+    // witness, without the reward: r H + c L_0 + sum_j alpha_j L_(2 + j) +
+    // sum_i counters_i L_(COUNTER_OFFSET + i)
     pub fn check_invariant(&self) {
-        let reward = self
-            .state
-            .counters
-            .iter()
-            .zip(self.config.prover.open_params.prices.iter())
-            .fold(C::OuterField::zero(), |acc, (x, y)| {
-                acc + &((*x as u64) * (*y as u64)).into()
-            });
-
-        let mut bases: Vec<_> = self.config.bba.lagrange_commitments.clone();
-        bases.push(self.config.bba.h);
-
-        let mut scalars = vec![self.state.c.into_repr(), reward.into_repr()];
-        // let mut scalars : Vec<<C::OuterField as PrimeField>::BigInt> =
-        scalars.extend(
-            self.state
-                .counters
-                .iter()
-                .map(|u| -> <C::OuterField as PrimeField>::BigInt { (*u as u64).into() })
-                .collect::<Vec<_>>(),
-        );
-        scalars.extend(vec![
-            C::OuterField::zero().into_repr();
-            proof_system::ZK_ROWS
-        ]);
-        scalars.push(self.state.r.into_repr());
+        let lg = &self.config.bba.lagrange_commitments;
+        let mut bases = vec![self.config.bba.h, lg[0]];
+        let mut scalars = vec![self.state.r.into_repr(), self.state.c.into_repr()];
+        for (j, a) in self.state.alpha.iter().enumerate() {
+            bases.push(lg[bba_open_proof::PUBLIC_INPUT + j]);
+            scalars.push(a.into_repr());
+        }
+        for (i, x) in self.state.counters.iter().enumerate() {
+            bases.push(lg[COUNTER_OFFSET + i]);
+            scalars.push((*x as u64).into());
+        }
 
-        assert_eq!(scalars.len(), bases.len());
         assert_eq!(
             VariableBaseMSM::multi_scalar_mul(bases.as_slice(), scalars.as_slice()).into_affine(),
-            self.state.acc + self.config.bba.lagrange_commitments[1].mul(reward).into()
+            self.state.acc
         );
     }
 
@@ -356,7 +526,7 @@ impl<'a, C: proof_system::Cycle> User<'a, C> {
 
         if !config
             .signer
-            .verify(config.authority_public_key, acc, signature)
+            .verify(config.authority_public_key, schnorr::point_message(&acc), signature)
         {
             return Err("init signature failed to verify");
         }
@@ -365,32 +535,37 @@ impl<'a, C: proof_system::Cycle> User<'a, C> {
         Ok(User {
             config,
             state: UserState {
-                r: secrets.r,
-                c: secrets.c,
-                alpha: secrets.alpha,
+                r: secrets.r.to_field(&config.bba.endo),
+                c: secrets.c.to_field(&config.bba.endo),
+                alpha: array_init(|i| secrets.alpha[i].to_field(&config.bba.endo)),
                 acc: acc,
                 counters,
                 signature,
-                pending_update_witness: None,
+                pending_updates: vec![],
+                redemption: None,
             },
         })
     }
 
+    // Opens the accumulator to its reward, leaving the wallet usable. This borrows
+    // the user mutably rather than taking &self because it records the opening in
+    // [UserState::redemption]: [accept_receipt] checks the authority's receipt
+    // against that record, and there is no other place the disclosed amounts and
+    // opening time could be kept for it without the caller carrying them around.
+    // The accumulator, its signature and the counters are not changed, and
+    // [restore] drops the record again.
     pub fn open<
         EFqSponge: Clone + FqSponge<C::InnerField, C::Inner, C::OuterField>,
         EFrSponge: FrSponge<C::OuterField>,
     >(
-        self,
-    ) -> RewardOpening<C> {
+        &mut self,
+    ) -> Result<RewardOpening<C>, &'static str> {
+        if let Some(Redemption::Redeemed(_)) = self.state.redemption {
+            return Err("Accumulator was already redeemed");
+        }
         let config = &self.config;
-        let reward = self
-            .state
-            .counters
-            .iter()
-            .zip(config.prover.open_params.prices.iter())
-            .fold(C::OuterField::zero(), |acc, (x, y)| {
-                acc + &((*x as u64) * (*y as u64)).into()
-            });
+        let (amounts, _) = config.prover.open_params.limbs(&self.state.counters);
+        let reward: C::OuterField = config.prover.open_params.total_value(&self.state.counters);
         let w = bba_open_proof::Witness {
             counters: self.state.counters.clone(),
             alpha: self.state.alpha.clone(),
@@ -409,59 +584,150 @@ impl<'a, C: proof_system::Cycle> User<'a, C> {
                 )
             },
         );
-        RewardOpening {
+        self.state.redemption = Some(Redemption::Opened {
+            nullifier: self.state.c,
+            amounts,
+            opened_at: unix_time(),
+        });
+        Ok(RewardOpening {
             proof,
             signature: self.state.signature,
+        })
+    }
+
+    // Makes the accumulator usable again after an opening which was never
+    // redeemed, e.g. because submitting it failed.
+    pub fn restore(&mut self) -> Result<(), &'static str> {
+        match self.state.redemption {
+            Some(Redemption::Redeemed(_)) => Err("Accumulator was already redeemed"),
+            _ => {
+                self.state.redemption = None;
+                Ok(())
+            }
+        }
+    }
+
+    // Records that the authority paid out the opening with [nullifier]. The
+    // accumulator cannot be opened again afterwards.
+    pub fn acknowledge_redemption(&mut self, nullifier: C::OuterField) -> Result<(), &'static str> {
+        match self.state.redemption {
+            Some(Redemption::Opened { nullifier: n, .. }) if n == nullifier => {
+                self.state.redemption = Some(Redemption::Redeemed(n));
+                Ok(())
+            }
+            _ => Err("No opening with this nullifier"),
+        }
+    }
+
+    // Checks that the current accumulator and its signature are entry [index] of the
+    // log with the signed tree head [head], given the audit path [proof]
+    pub fn verify_logged(
+        &self,
+        head: &SignedTreeHead<C::Inner>,
+        index: usize,
+        proof: &[C::InnerField],
+    ) -> bool {
+        let params = &self.config.signer.sponge;
+        let leaf = Entry::Accumulator(self.state.acc, self.state.signature).leaf_hash(params);
+        head.verify(&self.config.signer, self.config.authority_public_key)
+            && transparency_log::verify_inclusion(params, leaf, index, head.size, proof, head.root)
+    }
+
+    // Checks the authority's receipt for this accumulator's opening and records
+    // the redemption. The caller should keep the receipt as proof of payment.
+    pub fn accept_receipt(
+        &mut self,
+        receipt: &RedemptionReceipt<C::Inner>,
+    ) -> Result<(), &'static str> {
+        if !receipt.verify(&self.config.signer, self.config.authority_public_key) {
+            return Err("Receipt signature failed to verify");
         }
+        // A receipt for less than the opening discloses, or dated outside the
+        // time the payout could have happened, proves nothing in a dispute
+        match &self.state.redemption {
+            Some(Redemption::Opened {
+                amounts, opened_at, ..
+            }) => {
+                if receipt.amounts != *amounts {
+                    return Err("Receipt amounts differ from the opening");
+                }
+                if receipt.timestamp.saturating_add(RECEIPT_CLOCK_SKEW) < *opened_at
+                    || receipt.timestamp > unix_time().saturating_add(RECEIPT_CLOCK_SKEW)
+                {
+                    return Err("Receipt timestamp is outside the redemption period");
+                }
+            }
+            _ => return Err("No opening with this nullifier"),
+        }
+        self.acknowledge_redemption(receipt.nullifier)
     }
 
     pub fn process_update_response(
         &mut self,
-        updates: &Vec<SingleUpdate>,
         resp: &UpdateResponse<C::Inner>,
-    ) {
+    ) -> Result<(), &'static str> {
         let state = &mut self.state;
         let config = &self.config;
-        match state.pending_update_witness {
-            None => {
-                eprintln!("Unexpected update response");
-            }
-            Some(Randomized {
-                result: randomized_acc,
-                witness: r,
-            }) => {
-                assert_eq!(
-                    randomized_acc,
-                    state.acc + config.bba.h.mul(r.to_field(&config.bba.endo)).into_affine()
-                );
-                let updated_acc = update_delta(
-                    self.config.bba.lagrange_commitments.as_slice(),
-                    updates.as_slice(),
-                )
-                .add_mixed(&randomized_acc)
-                .into_affine();
+        let i = state
+            .pending_updates
+            .iter()
+            .position(|p| p.randomized.result == resp.randomized_acc)
+            .ok_or("Unexpected update response")?;
 
-                if config
-                    .signer
-                    .verify(config.authority_public_key, updated_acc, resp.signature)
-                {
-                    state.pending_update_witness = None;
-                    state.acc = updated_acc;
-                    state.signature = resp.signature;
-                    state.r += &r.to_field(&config.bba.endo);
-
-                    for SingleUpdate {
-                        campaign_index,
-                        delta,
-                    } in updates.iter()
-                    {
-                        state.counters[*campaign_index as usize] += delta;
-                    }
-                } else {
-                    eprintln!("Received invalid signature from update authority");
-                }
+        // The entry is kept, so the caller can tell which updates were lost before
+        // abandoning it
+        if state.pending_updates[i].base != state.acc {
+            return Err("Update response is for a superseded accumulator");
+        }
+
+        let pending = &state.pending_updates[i];
+        let r = pending.randomized.witness.to_field(&config.bba.endo);
+        assert_eq!(
+            pending.randomized.result,
+            state.acc + config.bba.h.mul(r).into_affine()
+        );
+        let lgr_comms = config.bba.lagrange_commitments.as_slice();
+        let delta = match &pending.delta {
+            None => update_delta(lgr_comms, pending.updates.as_slice()),
+            Some(d) => d.commitment.into_projective(),
+        };
+        let updated_acc = delta.add_mixed(&pending.randomized.result).into_affine();
+
+        // A response with an invalid signature may be a corrupted copy, so the
+        // entry is kept for a retransmission
+        if !config.signer.verify(
+            config.authority_public_key,
+            schnorr::point_message(&updated_acc),
+            resp.signature,
+        ) {
+            return Err("Received invalid signature from update authority");
+        }
+
+        let counters = apply_updates(&state.counters, &pending.updates)
+            .ok_or("Update response takes a counter out of range")?;
+        let pending = state.pending_updates.remove(i);
+
+        state.acc = updated_acc;
+        state.signature = resp.signature;
+        state.r += &r;
+        state.counters = counters;
+        if let Some(d) = pending.delta {
+            state.r += &d.r;
+            for (a, b) in state.alpha.iter_mut().zip(d.alpha.iter()) {
+                *a += b;
             }
         }
+        Ok(())
+    }
+
+    // Forgets a request whose response is not coming, e.g. because it was lost, or
+    // whose response was rejected as superseded. Returns whether the request was
+    // pending.
+    pub fn abandon_update(&mut self, randomized_acc: &C::Inner) -> bool {
+        let pending = &mut self.state.pending_updates;
+        let n = pending.len();
+        pending.retain(|p| p.randomized.result != *randomized_acc);
+        pending.len() != n
     }
 }
 
@@ -488,7 +754,13 @@ where
             None,
             vec![acc_x, acc_y],
             |sys, p| {
-                bba_init_proof::circuit::<_, G, _>(&self.prover.init_params, &Some(secrets), sys, p)
+                bba_init_proof::circuit::<_, G, _>(
+                    &self.prover.proof_system_constants,
+                    &self.prover.init_params,
+                    &Some(secrets),
+                    sys,
+                    p,
+                )
             },
         );
         InitRequest { acc, proof }
@@ -502,7 +774,15 @@ impl<'a, C: proof_system::Cycle> User<'a, C> {
     >(
         &mut self,
         updates: Vec<SingleUpdate>,
-    ) -> UpdateRequest<C::Inner, C::Outer> {
+    ) -> Result<UpdateRequest<C::Inner, C::Outer>, &'static str> {
+        // The nullifier of a redeemed accumulator is spent, so views counted on it
+        // could never be paid out
+        if let Some(Redemption::Redeemed(_)) = self.state.redemption {
+            return Err("Accumulator was already redeemed");
+        }
+        if apply_updates(&self.state.counters, &updates).is_none() {
+            return Err("Update takes a counter out of range");
+        }
         let config = &self.config;
         let state = &self.state;
         let randomization_witness = config.bba.randomize(state.acc);
@@ -528,12 +808,62 @@ impl<'a, C: proof_system::Cycle> User<'a, C> {
                 )
             },
         );
-        self.state.pending_update_witness = Some(randomization_witness);
-        UpdateRequest {
+        self.state.pending_updates.push(PendingUpdate {
+            base: self.state.acc,
+            randomized: randomization_witness,
+            updates: updates.clone(),
+            delta: None,
+        });
+        Ok(UpdateRequest {
             updates,
             proof,
             randomized_acc: new_acc,
+        })
+    }
+
+    // Like [request_update], but the authority only learns that no counter grows by
+    // more than one. Each campaign may appear in [updates] once, with a delta of one.
+    pub fn request_private_update<
+        EFqSponge: Clone + FqSponge<C::OuterField, C::Outer, C::InnerField>,
+        EFrSponge: FrSponge<C::InnerField>,
+        GFqSponge: Clone + FqSponge<C::InnerField, C::Inner, C::OuterField>,
+        GFrSponge: FrSponge<C::OuterField>,
+    >(
+        &mut self,
+        updates: Vec<SingleUpdate>,
+    ) -> Result<PrivateUpdateRequest<C::Inner, C::Outer>, &'static str> {
+        let deltas = apply_updates(&vec![0; MAX_COUNTERS], &updates)
+            .filter(|deltas| deltas.iter().all(|d| *d <= 1))
+            .ok_or("A private update adds at most one to each counter")?;
+        if apply_updates(&self.state.counters, &updates).is_none() {
+            return Err("Update takes a counter out of range");
         }
+        let update = self.request_update::<EFqSponge, EFrSponge>(vec![])?;
+
+        let rng = &mut rand_core::OsRng;
+        let r = C::OuterField::rand(rng);
+        let alpha = array_init(|_| C::OuterField::rand(rng));
+        let w = bba_delta_proof::Witness { deltas, alpha };
+        let config = &self.config;
+        let delta_proof = proof_system::prove::<C::Inner, _, GFqSponge, GFrSponge>(
+            &config.prover.delta_pk,
+            &config.prover.g_group_map,
+            Some([Some(r), None, None, None, None]),
+            vec![C::OuterField::zero(); bba_delta_proof::PUBLIC_INPUT],
+            |sys, p| bba_delta_proof::circuit(&Some(w), sys, p),
+        );
+
+        let pending = self.state.pending_updates.last_mut().unwrap();
+        pending.updates = updates;
+        pending.delta = Some(PrivateDelta {
+            commitment: delta_proof.commitments.w_comm[0].unshifted[0],
+            r,
+            alpha,
+        });
+        Ok(PrivateUpdateRequest {
+            update,
+            delta_proof,
+        })
     }
 }
 
@@ -591,18 +921,35 @@ where
         mut req: InitRequest<G, Other>,
     ) -> Result<schnorr::Signature<G>, &str> {
         let acc = match req.acc.to_coordinates() {
-            None => Err("Init bad acc"),
-            Some(p) => Ok(p),
-        }?;
+            None => {
+                self.metrics.rejected_request("init", "invalid_accumulator");
+                return Err("Init bad acc");
+            }
+            Some(acc) => acc,
+        };
         req.proof.public = vec![acc.0, acc.1];
-        match ProverProof::verify::<EFqSponge, EFrSponge>(
+        let start = Instant::now();
+        let verified = match ProverProof::verify::<EFqSponge, EFrSponge>(
             &self.group_map,
             &vec![(&self.init_vk, &self.big_other_lgr_comms, &req.proof)],
         ) {
             Ok(true) => Ok(()),
             Ok(false) | Err(_) => Err("Init proof failed to verify"),
-        }?;
-        Ok(self.signer.sign(self.signing_key, req.acc))
+        };
+        self.metrics.verified_batch("init", 1, start.elapsed());
+        self.metrics.proofs("init", verified, 1);
+        verified?;
+
+        let start = Instant::now();
+        let signature = self
+            .signer
+            .sign(self.signing_key, schnorr::point_message(&req.acc));
+        self.metrics.signed("init", 1, start.elapsed());
+        self.log
+            .lock()
+            .unwrap()
+            .append(Entry::Accumulator(req.acc, signature));
+        Ok(signature)
     }
 
     pub fn batch_init<
@@ -613,13 +960,44 @@ where
         mut reqs: Vec<InitRequest<G, Other>>,
     ) -> Result<Vec<schnorr::Signature<G>>, &str> {
         for req in reqs.iter_mut() {
-            let acc = match req.acc.to_coordinates() {
-                None => Err("Init bad acc"),
-                Some(p) => Ok(p),
-            }?;
-            req.proof.public = vec![acc.0, acc.1];
+            match req.acc.to_coordinates() {
+                None => {
+                    self.metrics.rejected_request("init", "invalid_accumulator");
+                    return Err("Init bad acc");
+                }
+                Some((x, y)) => req.proof.public = vec![x, y],
+            }
         }
 
+        let start = Instant::now();
+        let verified = self.verify_init_batch::<EFqSponge, EFrSponge>(&reqs);
+        self.metrics.verified_batch("init", reqs.len(), start.elapsed());
+        // The batch is accepted or rejected as a whole
+        self.metrics.proofs("init", verified, reqs.len());
+        verified?;
+
+        let start = Instant::now();
+        let accs : Vec<_> = reqs.iter().map(|r| r.acc).collect();
+        let signing_key = self.signing_key.clone();
+        let signer = self.signer.clone();
+        let res : Vec<_> = accs.par_iter().map(|acc| {
+            signer.sign(signing_key, schnorr::point_message(acc))
+        }).collect();
+        self.metrics.signed("init", res.len(), start.elapsed());
+        let mut log = self.log.lock().unwrap();
+        for (acc, signature) in accs.iter().zip(res.iter()) {
+            log.append(Entry::Accumulator(*acc, *signature));
+        }
+        Ok(res)
+    }
+
+    fn verify_init_batch<
+        EFqSponge: Clone + FqSponge<Other::BaseField, Other, Other::ScalarField>,
+        EFrSponge: FrSponge<Other::ScalarField>,
+    >(
+        &self,
+        reqs: &Vec<InitRequest<G, Other>>,
+    ) -> Result<(), &'static str> {
         let batch : Vec<_> = reqs.iter().map(|r| (&self.init_vk, &self.big_other_lgr_comms, &r.proof)).collect();
 
         match ProverProof::verify::<EFqSponge, EFrSponge>(
@@ -628,14 +1006,97 @@ where
         ) {
             Ok(true) => Ok(()),
             Ok(false) | Err(_) => Err("Init proofs failed to verify"),
-        }?;
-        let accs : Vec<_> = reqs.iter().map(|r| r.acc).collect();
-        let signing_key = self.signing_key.clone();
-        let signer = self.signer.clone();
-        let res : Vec<_> = accs.par_iter().map(|acc| {
-            signer.sign(signing_key, *acc)
-        }).collect();
-        Ok(res)
+        }
+    }
+
+    // Verifies a batch of openings with RewardOpening::verify_batch and returns
+    // their payouts, recording the outcome in [metrics]
+    pub fn verify_openings<
+        'b,
+        C: proof_system::Cycle<Inner = G>,
+        EFqSponge: Clone + FqSponge<C::InnerField, C::Inner, C::OuterField>,
+        EFrSponge: FrSponge<C::OuterField>,
+    >(
+        &self,
+        bba: &Params<G>,
+        group_map: &C::InnerMap,
+        vk: &VerifierIndex<'b, G>,
+        open_params: &bba_open_proof::Params,
+        openings: Vec<&RewardOpening<C>>,
+    ) -> Result<Vec<Payout<C>>, String> {
+        let authority_public_key = G::prime_subgroup_generator()
+            .mul(self.signing_key)
+            .into_affine();
+        let n = openings.len();
+        let start = Instant::now();
+        let payouts = RewardOpening::verify_batch::<EFqSponge, EFrSponge>(
+            &self.signer,
+            bba,
+            authority_public_key,
+            group_map,
+            vk,
+            openings.clone(),
+        )
+        .and_then(|()| {
+            openings
+                .iter()
+                .map(|o| o.payout(open_params).map_err(String::from))
+                .collect()
+        });
+        self.metrics.verified_batch("open", n, start.elapsed());
+        self.metrics
+            .proofs("open", payouts.as_ref().map(|_| ()).map_err(|e| e.as_str()), n);
+        payouts
+    }
+
+    // Signs a receipt for a payout returned by [verify_openings], once it has been
+    // paid out, timestamped with the authority's clock. A nullifier gets at most one
+    // receipt, so a replayed opening cannot be paid out twice.
+    pub fn issue_receipt<C: proof_system::Cycle<Inner = G, OuterField = G::ScalarField>>(
+        &self,
+        payout: &Payout<C>,
+    ) -> Result<RedemptionReceipt<G>, &'static str> {
+        let nullifier = payout.nullifier;
+        let index = {
+            let mut log = self.log.lock().unwrap();
+            let entry = Entry::Redemption(nullifier);
+            if log.index_of(&entry).is_some() {
+                return Err("Nullifier was already redeemed");
+            }
+            log.append(entry)
+        };
+        let timestamp = unix_time();
+        let m = receipt_message::<G>(nullifier, &payout.amounts, timestamp);
+        let start = Instant::now();
+        let signature = self.signer.sign(self.signing_key, m);
+        self.metrics.signed("receipt", 1, start.elapsed());
+        self.metrics.event(
+            "receipt_issued",
+            &[
+                ("nullifier", field_hex(&nullifier)),
+                ("log_index", index.to_string()),
+                ("timestamp", timestamp.to_string()),
+            ],
+        );
+        Ok(RedemptionReceipt {
+            nullifier,
+            amounts: payout.amounts.clone(),
+            timestamp,
+            signature,
+        })
+    }
+
+    // The current root of the log, which users check their accumulators against
+    // and auditors check to only ever grow (see transparency_log)
+    pub fn signed_tree_head(&self) -> SignedTreeHead<G> {
+        let start = Instant::now();
+        let head = SignedTreeHead::sign(&self.signer, self.signing_key, &self.log.lock().unwrap());
+        self.metrics.signed("tree_head", 1, start.elapsed());
+        self.metrics.event(
+            "tree_head",
+            &[("size", head.size.to_string()), ("root", field_hex(&head.root))],
+        );
+        head
     }
 
     // This function is batched for efficiency of proof verification
@@ -644,15 +1105,87 @@ where
         EFrSponge: FrSponge<Other::ScalarField>,
     >(
         &self,
-        mut reqs: Vec<UpdateRequest<G, Other>>,
+        reqs: Vec<UpdateRequest<G, Other>>,
     ) -> Vec<Result<UpdateResponse<G>, &str>> {
+        let deltas = vec![None; reqs.len()];
+        self.answer_updates::<EFqSponge, EFrSponge>(reqs, deltas)
+    }
+
+    // Verifies the delta proofs of [reqs] against [delta_vk], and answers the
+    // requests whose proof verifies like [perform_updates], adding the delta
+    // commitment to their accumulators
+    pub fn perform_private_updates<
+        'b,
+        EFqSponge: Clone + FqSponge<Other::BaseField, Other, Other::ScalarField>,
+        EFrSponge: FrSponge<Other::ScalarField>,
+        GFqSponge: Clone + FqSponge<G::BaseField, G, G::ScalarField>,
+        GFrSponge: FrSponge<G::ScalarField>,
+    >(
+        &self,
+        group_map: &G::Map,
+        delta_vk: &VerifierIndex<'b, G>,
+        mut reqs: Vec<PrivateUpdateRequest<G, Other>>,
+    ) -> Vec<Result<UpdateResponse<G>, &str>> {
+        let lgr_comms: Vec<PolyComm<G>> = self
+            .lgr_comms
+            .iter()
+            .map(|g| PolyComm {
+                unshifted: vec![*g],
+                shifted: None,
+            })
+            .collect();
+        for req in reqs.iter_mut() {
+            req.delta_proof.public = vec![G::ScalarField::zero(); bba_delta_proof::PUBLIC_INPUT];
+        }
+        let batch = reqs
+            .iter()
+            .map(|r| (delta_vk, &lgr_comms, &r.delta_proof))
+            .collect();
+        let start = Instant::now();
+        let verified = batch_verify_proofs::<G, GFqSponge, GFrSponge>(group_map, batch);
+        self.metrics.verified_batch("delta", reqs.len(), start.elapsed());
+
+        let mut results = vec![];
+        let mut indices = vec![];
+        let mut updates = vec![];
+        let mut deltas = vec![];
+        for (i, (req, ok)) in reqs.into_iter().zip(verified).enumerate() {
+            if ok {
+                self.metrics.proofs("delta", Ok(()), 1);
+                indices.push(i);
+                deltas.push(Some(req.delta_proof.commitments.w_comm[0].unshifted[0]));
+                updates.push(req.update);
+            } else {
+                self.metrics.proofs("delta", Err("Delta proof failed to verify"), 1);
+            }
+            results.push(Err("Delta proof failed to verify"));
+        }
+        let answers = self.answer_updates::<EFqSponge, EFrSponge>(updates, deltas);
+        for (i, answer) in indices.into_iter().zip(answers) {
+            results[i] = answer;
+        }
+        results
+    }
+
+    // Answers [reqs], adding [deltas[i]], if any, to the accumulator of [reqs[i]]
+    fn answer_updates<
+        EFqSponge: Clone + FqSponge<Other::BaseField, Other, Other::ScalarField>,
+        EFrSponge: FrSponge<Other::ScalarField>,
+    >(
+        &self,
+        mut reqs: Vec<UpdateRequest<G, Other>>,
+        deltas: Vec<Option<G>>,
+    ) -> Vec<Result<UpdateResponse<G>, &'static str>> {
         let mut results = vec![Err(""); reqs.len()];
 
         let mut batch_indices = vec![];
         let mut batch = vec![];
         for (i, req) in reqs.iter_mut().enumerate() {
             match req.randomized_acc.to_coordinates() {
-                None => results[i] = Err("Invalid accumulator in request"),
+                None => {
+                    self.metrics.rejected_request("update", "invalid_accumulator");
+                    results[i] = Err("Invalid accumulator in request")
+                }
                 Some((x, y)) => {
                     req.proof.public = vec![x, y];
                     batch_indices.push(i);
@@ -662,7 +1195,10 @@ where
         }
 
         let mut success_indices = vec![];
+        let start = Instant::now();
+        let batch_size = batch.len();
         let verify_results = batch_verify_proofs::<_, EFqSponge, EFrSponge>(&self.group_map, batch);
+        self.metrics.verified_batch("update", batch_size, start.elapsed());
         for (&i, verified) in batch_indices.iter().zip(verify_results) {
             if verified {
                 success_indices.push(i);
@@ -670,18 +1206,37 @@ where
                 results[i] = Err("Update proof failed to verify");
             }
         }
+        let signed = success_indices.len();
 
+        let start = Instant::now();
         for i in success_indices {
             let req = &reqs[i];
 
-            let delta = update_delta(&self.lgr_comms[..], &req.updates[..]);
+            let mut delta = update_delta(&self.lgr_comms[..], &req.updates[..]);
+            if let Some(d) = deltas[i] {
+                delta = delta.add_mixed(&d);
+            }
 
             let new_acc = delta.add_mixed(&req.randomized_acc).into_affine();
 
+            let signature = self
+                .signer
+                .sign(self.signing_key, schnorr::point_message(&new_acc));
+            self.log
+                .lock()
+                .unwrap()
+                .append(Entry::Accumulator(new_acc, signature));
+
             results[i] = Ok(UpdateResponse::<G> {
-                signature: self.signer.sign(self.signing_key, new_acc),
+                randomized_acc: req.randomized_acc,
+                signature,
             });
         }
+        self.metrics.signed("update", signed, start.elapsed());
+        for &i in batch_indices.iter() {
+            let r = &results[i];
+            self.metrics.proofs("update", r.as_ref().map(|_| ()).map_err(|e| *e), 1);
+        }
 
         results
     }
@@ -0,0 +1,38 @@
+use crate::bba;
+use crate::bba_open_proof;
+use crate::proof_system::*;
+use algebra::{FftField, PrimeField};
+
+// A private update sends the commitment to column 0 of this circuit's witness,
+// which has the layout of an accumulator: the rows of the nullifier and the
+// reward are public inputs fixed to zero by the verifier, the zk rows hold a
+// blinding to add to the accumulator's, and the rows of the counters hold the
+// deltas. Adding the commitment to an accumulator adds the deltas to its counters.
+pub const PUBLIC_INPUT: usize = bba_open_proof::PUBLIC_INPUT;
+
+#[derive(Clone)]
+pub struct Witness<F> {
+    // One per counter, each 0 or 1
+    pub deltas: Vec<u32>,
+    pub alpha: [F; ZK_ROWS],
+}
+
+// Every counter row is the opening circuit's, so the domain has room for one
+// Generic relation per delta and nothing else (see "The opening domain is full"
+// in the README). That is enough for delta_i - delta_i^2 = 0, bounding each delta
+// by one, but not for a bound on their sum, so the number of views a private
+// update adds is only bounded by bba::MAX_COUNTERS.
+pub fn circuit<F: PrimeField + FftField, Sys: Cs<F>>(
+    w: &Option<Witness<F>>,
+    sys: &mut Sys,
+    _public_input: Vec<Var<F>>,
+) {
+    bba_open_proof::zk_rows(w.as_ref().map(|w| &w.alpha), sys);
+
+    sys.scope("deltas", |sys| {
+        for i in 0..bba::MAX_COUNTERS {
+            let delta = sys.var(|| F::from(w.as_ref().unwrap().deltas[i] as u64));
+            sys.assert_boolean(Bits::unconstrained(vec![delta]));
+        }
+    });
+}
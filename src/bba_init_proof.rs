@@ -1,3 +1,5 @@
+use crate::circuit_vars::{PointVar, ScalarVar};
+use crate::endo::EndoScalar;
 use crate::proof_system::*;
 use crate::schnorr;
 use algebra::{AffineCurve, FftField, PrimeField};
@@ -12,6 +14,13 @@ use schnorr::CoordinateCurve;
 //   [c : scalar],
 //   [alpha : scalar[5]] such that
 //   [acc = r * H + c * L_0 + alpha[0] * L_2 + .. + alpha[4] * L_6]
+//
+// The scalars are sampled as endo::EndoScalar so that every multiplication, all of
+// which are by constant bases, goes through the Endomul gadget at one row per bit
+// instead of two for Vbmul1. Fixed-base windowed multiplication does not beat that
+// with these gates: a 2-bit window costs a Generic row per table coordinate, a
+// Generic row per booleanity check and two rows for the addition, i.e. three rows
+// per bit.
 
 #[derive(Copy, Clone)]
 pub struct Params<G> {
@@ -21,9 +30,9 @@ pub struct Params<G> {
 
 #[derive(Copy, Clone)]
 pub struct Witness<G: AffineCurve> {
-    pub r: G::ScalarField,
-    pub c: G::ScalarField,
-    pub alpha: [G::ScalarField; ZK_ROWS],
+    pub r: EndoScalar<G::ScalarField>,
+    pub c: EndoScalar<G::ScalarField>,
+    pub alpha: [EndoScalar<G::ScalarField>; ZK_ROWS],
 }
 
 pub fn circuit<
@@ -31,50 +40,37 @@ pub fn circuit<
     G: AffineCurve<BaseField = F> + CoordinateCurve,
     Sys: Cs<F>,
 >(
+    constants: &Constants<F>,
     params: &Params<G>,
     w: &Option<Witness<G>>,
     sys: &mut Sys,
     public_input: Vec<Var<F>>,
 ) {
-    let constant_curve_pt = |sys: &mut Sys, (x, y)| {
-        let x = sys.constant(x);
-        let y = sys.constant(y);
-        (x, y)
-    };
-    let len = G::ScalarField::size_in_bits();
-    let shift: G::ScalarField = shift::<G::ScalarField>(len - 1);
-    let rh = {
-        let h = constant_curve_pt(sys, params.h.to_coords().unwrap());
-        let r = sys.scalar(len, || (w.as_ref().unwrap().r - &shift).into_repr());
-        sys.scalar_mul(h, r)
-    };
-    let cl0 = {
-        let l0 = constant_curve_pt(sys, params.lagrange_commitments[0].to_coords().unwrap());
-        let c = sys.scalar(len, || (w.as_ref().unwrap().c - &shift).into_repr());
-        sys.scalar_mul(l0, c)
+    let endo_mul = |sys: &mut Sys, base: G, s: Option<EndoScalar<G::ScalarField>>| {
+        let base = PointVar::constant(sys, base.to_coords().unwrap());
+        let s = ScalarVar::witness(sys, 256, || s.unwrap().0.into_repr());
+        base.endo_mul(sys, constants, s)
     };
+    let rh = sys.scope("rh", |sys| endo_mul(sys, params.h, w.map(|w| w.r)));
+    let cl0 = sys.scope("cl0", |sys| {
+        endo_mul(sys, params.lagrange_commitments[0], w.map(|w| w.c))
+    });
 
-    let alpha_point = |sys: &mut Sys, i| {
-        let g: G = params.lagrange_commitments[2 + i];
-        let g: (F, F) = g.to_coords().unwrap();
-        let base = constant_curve_pt(sys, g);
-        let alpha_i = sys.scalar(len, || {
-            let a: G::ScalarField = w.as_ref().unwrap().alpha[i] - &shift;
-            a.into_repr()
-        });
-        sys.scalar_mul(base, alpha_i)
+    let alpha_point = |sys: &mut Sys, i: usize| {
+        endo_mul(sys, params.lagrange_commitments[2 + i], w.map(|w| w.alpha[i]))
     };
 
-    let alpha_part = {
+    let alpha_part = sys.scope("alpha", |sys| {
         let mut acc = alpha_point(sys, 0);
         for i in 1..5 {
             let alpha_pt = alpha_point(sys, i);
-            let new_acc = sys.add_group(acc, alpha_pt);
-            acc = new_acc
+            acc = acc.add(sys, alpha_pt);
         }
         acc
-    };
-    let rh_cl0 = sys.add_group(rh, cl0);
-    sys.assert_add_group(rh_cl0, alpha_part, (public_input[0], public_input[1]));
-    sys.zk()
+    });
+    sys.scope("sum", |sys| {
+        let acc = PointVar::new((public_input[0], public_input[1]));
+        rh.add(sys, cl0).assert_add(sys, alpha_part, acc)
+    });
+    sys.scope("zk", |sys| sys.zk())
 }
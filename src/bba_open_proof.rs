@@ -9,17 +9,151 @@ use schnorr::CoordinateCurve;
 // c, total value
 pub const PUBLIC_INPUT: usize = 2;
 
-// Parameters for the update proof circuit.
+// The total value packs one 64-bit limb per price schedule, holding the reward
+// under that schedule, followed by one limb per category, holding the counter sum
+// of the category. The circuit has no rows to range-check the limbs (see "The
+// opening domain is full" in the README), so instead the prices of each
+// schedule must sum to less than MAX_SCHEDULE_PRICE, which keeps limbs from
+// carrying while counters are below 2^32. Honest clients keep them there (User::request_update refuses updates that
+// overflow a counter), but the authority cannot enforce it: it only sees the
+// deltas of each update, not the counters they add up to. Past about 2^34 views
+// of one slot a reward can carry into the next limb, so openings with more than
+// one limb are only as sound as the clients. With a single schedule and no
+// categories a carry lands in a limb which [Params::unpack_total_value] requires
+// to be zero, and the opening is rejected.
+pub const MAX_LIMBS: usize = 4;
+pub const MAX_SCHEDULE_PRICE: u64 = 1 << 30;
+
+// Parameters for the opening proof circuit. The schedules and categories are baked
+// into the circuit, so each of their combinations needs its own proving key.
 #[derive(Clone)]
 pub struct Params {
-    pub prices: Vec<u32>,
+    prices: Vec<Vec<u32>>,
+    categories: Vec<Vec<usize>>,
+    // The limbs of the coefficient of each counter in the total value
+    weights: Vec<[u64; MAX_LIMBS]>,
+}
+
+impl Params {
+    // [prices[k][i]] is the price of a view of campaign i under schedule k, e.g. for
+    // payouts in different tokens or regions. Each of [categories] lists the counter
+    // slots whose sum an opening discloses; a category with a single slot discloses
+    // that counter. This is a policy of the proving key, not something the user
+    // picks per opening (see the README).
+    pub fn new(prices: Vec<Vec<u32>>, categories: Vec<Vec<usize>>) -> Self {
+        assert!(!prices.is_empty());
+        assert!(prices.len() + categories.len() <= MAX_LIMBS);
+        assert!(prices.iter().all(|schedule| {
+            schedule.len() == bba::MAX_COUNTERS
+                && schedule.iter().map(|p| *p as u64).sum::<u64>() < MAX_SCHEDULE_PRICE
+        }));
+        assert!(categories
+            .iter()
+            .all(|slots| slots.iter().all(|i| *i < bba::MAX_COUNTERS)));
+
+        let weights = (0..bba::MAX_COUNTERS)
+            .map(|i| {
+                let mut w = [0; MAX_LIMBS];
+                for (k, schedule) in prices.iter().enumerate() {
+                    w[k] = schedule[i] as u64;
+                }
+                for (j, slots) in categories.iter().enumerate() {
+                    w[prices.len() + j] = slots.contains(&i) as u64;
+                }
+                w
+            })
+            .collect();
+        Params {
+            prices,
+            categories,
+            weights,
+        }
+    }
+
+    pub fn prices(&self) -> &[Vec<u32>] {
+        &self.prices
+    }
+
+    pub fn categories(&self) -> &[Vec<usize>] {
+        &self.categories
+    }
+
+    // The coefficient of counter i in the total value
+    pub fn weight<F: PrimeField>(&self, i: usize) -> F {
+        let limb = F::from(2u64).pow(&[64]);
+        self.weights[i]
+            .iter()
+            .rev()
+            .fold(F::zero(), |acc, w| acc * &limb + &F::from(*w))
+    }
+
+    pub fn total_value<F: PrimeField>(&self, counters: &[u32]) -> F {
+        counters
+            .iter()
+            .enumerate()
+            .fold(F::zero(), |acc, (i, x)| {
+                acc + &(self.weight::<F>(i) * &F::from(*x as u64))
+            })
+    }
+
+    // The reward under each schedule and the sum of each category for [counters].
+    // With u32 counters none of them fills its limb (see MAX_SCHEDULE_PRICE).
+    pub fn limbs(&self, counters: &[u32]) -> (Vec<u64>, Vec<u64>) {
+        let limb = |j: usize| -> u64 {
+            counters
+                .iter()
+                .zip(self.weights.iter())
+                .map(|(c, w)| *c as u64 * w[j])
+                .sum()
+        };
+        let k = self.prices.len();
+        let rewards = (0..k).map(&limb).collect();
+        let sums = (k..k + self.categories.len()).map(&limb).collect();
+        (rewards, sums)
+    }
+
+    // Splits a total value into the reward under each schedule and the category
+    // sums, or None if it does not have the layout of [total_value].
+    pub fn unpack_total_value<F: PrimeField>(&self, x: F) -> Option<(Vec<u64>, Vec<u64>)> {
+        let (k, n) = (self.prices.len(), self.categories.len());
+        let repr = x.into_repr();
+        let limbs = repr.as_ref();
+        if limbs[k + n..].iter().any(|l| *l != 0) {
+            return None;
+        }
+        Some((limbs[..k].to_vec(), limbs[k..k + n].to_vec()))
+    }
 }
 
+#[derive(Clone)]
 pub struct Witness<F> {
     pub counters: Vec<u32>,
     pub alpha: [F; ZK_ROWS],
 }
 
+// The ZK_ROWS rows after the public input. Column 0 holds [alpha], the slots of
+// the accumulator which blind the proof's evaluations of it, and the other
+// columns are random.
+pub fn zk_rows<F: PrimeField + FftField, Sys: Cs<F>>(alpha: Option<&[F; ZK_ROWS]>, sys: &mut Sys) {
+    sys.scope("zk", |sys| {
+        for r in 0..ZK_ROWS {
+            let row = array_init(|i| {
+                if i == 0 {
+                    sys.var(|| alpha.unwrap()[r])
+                } else {
+                    sys.var(|| F::rand(&mut rand_core::OsRng))
+                }
+            });
+
+            sys.gate(GateSpec {
+                typ: GateType::Generic,
+                c: vec![F::zero(); COLUMNS + 2],
+                row,
+            });
+        }
+    });
+}
+
 pub fn circuit<
     F: PrimeField + FftField,
     G: AffineCurve<BaseField = F> + CoordinateCurve,
@@ -30,80 +164,65 @@ pub fn circuit<
     sys: &mut Sys,
     public_input: Vec<Var<F>>,
 ) {
-    for r in 0..ZK_ROWS {
-        let row = array_init(|i| {
-            if i == 0 {
-                sys.var(|| w.as_ref().unwrap().alpha[r])
-            } else {
-                sys.var(|| F::rand(&mut rand_core::OsRng))
-            }
-        });
-
-        sys.gate(GateSpec {
-            typ: GateType::Generic,
-            c: vec![
-                F::zero(),
-                F::zero(),
-                F::zero(),
-                F::zero(),
-                F::zero(),
-                F::zero(),
-                F::zero(),
-            ],
-            row,
-        });
-    }
+    zk_rows(w.as_ref().map(|w| &w.alpha), sys);
 
-    let counter = |i| F::from(w.as_ref().unwrap().counters[i] as u64);
-    let price = |i| F::from(params.prices[i] as u64);
-    let mut acc = sys.var(|| counter(0) * price(0));
-    let row0 = [
-        sys.var(|| counter(0)),
-        acc,
-        sys.var(|| F::zero()),
-        sys.var(|| F::zero()),
-        sys.var(|| F::zero()),
-    ];
-    sys.gate(GateSpec {
-        typ: GateType::Generic,
-        row: row0,
-        c: vec![
-            price(0),
-            -F::one(),
-            F::zero(),
-            F::zero(),
-            F::zero(),
-            F::zero(),
-            F::zero(),
-        ],
-    });
-
-    for i in 1..bba::MAX_COUNTERS {
-        let new_acc = if i == bba::MAX_COUNTERS - 1 {
-            public_input[1]
-        } else {
-            sys.var(|| acc.val() + counter(i) * price(i))
-        };
-        let row = [
-            sys.var(|| counter(i)),
+    // Counter i must sit in column 0 of row COUNTER_OFFSET + i, since the
+    // commitment to column 0 is checked against the signed accumulator, whose
+    // counters are coefficients of the corresponding Lagrange bases. The running
+    // sum shares each counter's row, so this part of the circuit is already one
+    // row per counter and the domain bounds bba::MAX_COUNTERS.
+    sys.scope("counters", |sys| {
+        let counter = |i| F::from(w.as_ref().unwrap().counters[i] as u64);
+        let weight = |i| params.weight::<F>(i);
+        let mut acc = sys.var(|| counter(0) * weight(0));
+        let row0 = [
+            sys.var(|| counter(0)),
             acc,
-            new_acc,
+            sys.var(|| F::zero()),
             sys.var(|| F::zero()),
             sys.var(|| F::zero()),
         ];
         sys.gate(GateSpec {
             typ: GateType::Generic,
-            row: row,
+            row: row0,
             c: vec![
-                price(i),
-                F::one(),
+                weight(0),
                 -F::one(),
                 F::zero(),
                 F::zero(),
                 F::zero(),
                 F::zero(),
+                F::zero(),
             ],
         });
-        acc = new_acc;
-    }
+
+        for i in 1..bba::MAX_COUNTERS {
+            let new_acc = if i == bba::MAX_COUNTERS - 1 {
+                public_input[1]
+            } else {
+                sys.var(|| acc.val() + counter(i) * weight(i))
+            };
+            let row = [
+                sys.var(|| counter(i)),
+                acc,
+                new_acc,
+                sys.var(|| F::zero()),
+                sys.var(|| F::zero()),
+            ];
+            sys.gate(GateSpec {
+                typ: GateType::Generic,
+                row: row,
+                c: vec![
+                    weight(i),
+                    F::one(),
+                    -F::one(),
+                    F::zero(),
+                    F::zero(),
+                    F::zero(),
+                    F::zero(),
+                ],
+            });
+            acc = new_acc;
+        }
+    });
 }
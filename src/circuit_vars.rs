@@ -0,0 +1,175 @@
+use crate::proof_system::*;
+use crate::schnorr;
+use algebra::{BigInteger, FftField, One, PrimeField, Zero};
+
+// Typed wrappers around the variables the circuits pass to the gadgets in [Cs], so
+// that points, scalars and booleans cannot be confused with each other.
+
+// A curve point, given by its affine coordinates.
+#[derive(Clone, Copy, Debug)]
+pub struct PointVar<F> {
+    pub x: Var<F>,
+    pub y: Var<F>,
+}
+
+// A scalar, given by its bits least significant first.
+pub struct ScalarVar<F, S> {
+    bits: Bits<F, S>,
+}
+
+// A variable constrained to be 0 or 1.
+#[derive(Clone, Copy, Debug)]
+pub struct BoolVar<F>(Var<F>);
+
+impl<F: FftField> PointVar<F> {
+    pub fn new((x, y): (Var<F>, Var<F>)) -> Self {
+        PointVar { x, y }
+    }
+
+    pub fn constant<Sys: Cs<F>>(sys: &mut Sys, (x, y): (F, F)) -> Self {
+        PointVar {
+            x: sys.constant(x),
+            y: sys.constant(y),
+        }
+    }
+
+    pub fn witness<Sys: Cs<F>, G>(sys: &mut Sys, g: G) -> Self
+    where
+        G: FnOnce() -> (F, F),
+    {
+        let mut p = None;
+        let x = sys.var(|| {
+            let coords = g();
+            p = Some(coords);
+            coords.0
+        });
+        let y = sys.var(|| p.unwrap().1);
+        PointVar { x, y }
+    }
+
+    pub fn coords(&self) -> (Var<F>, Var<F>) {
+        (self.x, self.y)
+    }
+
+    pub fn add<Sys: Cs<F>>(self, sys: &mut Sys, other: Self) -> Self {
+        Self::new(sys.add_group(self.coords(), other.coords()))
+    }
+
+    // Asserts that [self] + [other] = [res]
+    pub fn assert_add<Sys: Cs<F>>(self, sys: &mut Sys, other: Self, res: Self) {
+        sys.assert_add_group(self.coords(), other.coords(), res.coords())
+    }
+
+    pub fn double<Sys: Cs<F>>(self, sys: &mut Sys) -> Self {
+        Self::new(sys.double(self.coords()))
+    }
+
+    pub fn neg<Sys: Cs<F>>(self, sys: &mut Sys) -> Self {
+        PointVar {
+            x: self.x,
+            y: sys.scale(-F::one(), self.y),
+        }
+    }
+
+    // [s] is the bit decomposition of s - 2^(len - 1), see [Cs::scalar_mul]
+    pub fn scale<Sys: Cs<F>, S>(self, sys: &mut Sys, s: ScalarVar<F, S>) -> Self {
+        Self::new(sys.scalar_mul(self.coords(), s.bits))
+    }
+
+    // Multiplies by the endomorphism-encoded scalar [s], see endo::EndoScalar
+    pub fn endo_mul<Sys: Cs<F>, S>(
+        self,
+        sys: &mut Sys,
+        constants: &Constants<F>,
+        s: ScalarVar<F, S>,
+    ) -> Self {
+        Self::new(sys.endo(constants, self.coords(), s.bits))
+    }
+}
+
+impl<F: FftField> ScalarVar<F, Unconstrained> {
+    pub fn witness<Sys: Cs<F>, G, N: BigInteger>(sys: &mut Sys, length: usize, g: G) -> Self
+    where
+        G: FnOnce() -> N,
+    {
+        ScalarVar {
+            bits: sys.scalar(length, g),
+        }
+    }
+}
+
+impl<F: FftField, S> ScalarVar<F, S> {
+    // Asserts that the bits of [self] are those of [x]
+    pub fn pack<Sys: Cs<F>>(self, sys: &mut Sys, x: Var<F>) -> ScalarVar<F, Boolean> {
+        ScalarVar {
+            bits: sys.assert_pack(x, self.bits),
+        }
+    }
+
+    pub fn into_bits(self) -> Bits<F, S> {
+        self.bits
+    }
+}
+
+impl<F: FftField> ScalarVar<F, Boolean> {
+    pub fn bit(&self, i: usize) -> BoolVar<F> {
+        BoolVar(self.bits[i])
+    }
+}
+
+impl<F: FftField> BoolVar<F> {
+    pub fn witness<Sys: Cs<F>, G>(sys: &mut Sys, g: G) -> Self
+    where
+        G: FnOnce() -> bool,
+    {
+        let b = sys.var(|| if g() { F::one() } else { F::zero() });
+        let bits = sys.assert_boolean(Bits::unconstrained(vec![b]));
+        BoolVar(bits[0])
+    }
+
+    pub fn var(&self) -> Var<F> {
+        self.0
+    }
+
+    // [t] if [self] is 1 and [f] otherwise
+    pub fn select<Sys: Cs<F>>(self, sys: &mut Sys, t: Var<F>, f: Var<F>) -> Var<F> {
+        sys.cond_select(self.0, t, f)
+    }
+}
+
+// Asserts that (r, s) is a signature on [message] under [pubkey] which
+// schnorr::Signer::verify would accept. [s] is the bit decomposition of
+// s - 2^(len - 1), as expected by [Cs::scalar_mul].
+pub fn assert_signature<F: PrimeField + FftField, Sys: Cs<F>, S>(
+    sys: &mut Sys,
+    constants: &Constants<F>,
+    pubkey: PointVar<F>,
+    message: &[Var<F>],
+    r: Var<F>,
+    s: ScalarVar<F, S>,
+) {
+    let zero = sys.constant(F::zero());
+    let e = sys.scope("e", |sys| {
+        let mut input = vec![sys.constant(schnorr::hash_domain(message.len()))];
+        input.extend_from_slice(message);
+        input.push(r);
+        let e = sys.hash(constants, &input);
+        ScalarVar::witness(sys, 256, || e.val().into_repr()).pack(sys, e)
+    });
+    let neg_e_pk = sys.scope("e_pk", |sys| {
+        // optimization: Could save a constraint by not explicitly doing the negation and using
+        // assert_add
+        pubkey.endo_mul(sys, constants, e).neg(sys)
+    });
+    let r_pt = sys.scope("s_g", |sys| {
+        let base = PointVar::constant(sys, constants.base);
+        // TODO: Need to check that s is not one of the two forbidden values
+        base.scale(sys, s).add(sys, neg_e_pk)
+    });
+    // optimization: Could save a constraint in constraining y to be even
+    let ry_bits = sys.scope("r_even", |sys| {
+        ScalarVar::witness(sys, 256, || r_pt.y.val().into_repr()).pack(sys, r_pt.y)
+    });
+    sys.assert_eq(zero, ry_bits.bit(0).var());
+    sys.assert_eq(r_pt.x, r);
+}
@@ -0,0 +1,14 @@
+pub mod bba;
+pub mod bba_delta_proof;
+pub mod bba_init_proof;
+pub mod bba_open_proof;
+pub mod bba_update_proof;
+pub mod circuit_vars;
+pub mod endo;
+pub mod fft;
+pub mod metrics;
+pub mod proof_system;
+pub mod random_oracle;
+pub mod schnorr;
+pub mod transparency_log;
+pub mod util;
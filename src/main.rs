@@ -5,7 +5,7 @@ use algebra::{
         pallas::{Affine as Other, PallasParameters},
         vesta::{Affine, VestaParameters},
     },
-    AffineCurve, ProjectiveCurve, UniformRand,
+    AffineCurve, Field, One, ProjectiveCurve, UniformRand, Zero,
 };
 use array_init::array_init;
 use commitment_dlog::{
@@ -18,16 +18,10 @@ use oracle::{
     sponge_5_wires::{DefaultFqSponge, DefaultFrSponge},
 };
 
-mod bba;
-mod bba_init_proof;
-mod bba_open_proof;
-mod bba_update_proof;
-mod endo;
-mod fft;
-mod proof_system;
-mod random_oracle;
-mod schnorr;
-mod util;
+use brave_poc::{
+    bba, bba_delta_proof, bba_init_proof, bba_open_proof, bba_update_proof, fft, metrics,
+    proof_system, random_oracle, schnorr, transparency_log, util,
+};
 use proof_system::*;
 use util::*;
 
@@ -46,11 +40,61 @@ fn main() {
         endo: endo_r,
     };
     {
-        let m = Other::prime_subgroup_generator();
+        let pt = Other::prime_subgroup_generator();
+        let m = point_message(&pt);
         let k = <Other as AffineCurve>::ScalarField::rand(&mut rand_core::OsRng);
         let pubkey = Other::prime_subgroup_generator().mul(k).into_affine();
-        let s = signer.sign(k, m);
-        assert!(signer.verify(pubkey, m, s));
+        let s = signer.sign(k, m.clone());
+        assert!(signer.verify(pubkey, m.clone(), s));
+
+        // Nonces are derived from the key and message, so signing is deterministic
+        assert!(s == signer.sign(k, m.clone()));
+        let m2 = point_message(&pt.mul(k).into_affine());
+        let s2 = signer.sign(k, m2.clone());
+        assert!(s2.0 != s.0);
+        assert!(signer.verify(pubkey, m2, s2));
+        assert!(!signer.verify(pubkey, m.clone(), s2));
+
+        // Messages can carry extra field elements, and their length is bound by the hash
+        let mut m3 = m.clone();
+        m3.push(Fp::from(7 as u64));
+        let s3 = signer.sign(k, m3.clone());
+        assert!(signer.verify(pubkey, m3, s3));
+        let mut m4 = m.clone();
+        m4.push(Fp::zero());
+        assert!(!signer.verify(pubkey, m4, s));
+    }
+
+    {
+        // Known-answer vectors for fixed keys and messages, so that any change to the
+        // nonce derivation or the challenge hash is caught. src/schnorr_vectors.txt
+        // holds the expected "r s" of each vector, one per line, in field_hex. After
+        // an intended change to signing, run with BBA_UPDATE_VECTORS set to rewrite it.
+        let g = Other::prime_subgroup_generator();
+        let vectors: Vec<(Fq, Vec<Fp>)> = vec![
+            (Fq::from(1u64), point_message(&g)),
+            (Fq::from(2u64), point_message(&g.mul(Fq::from(3u64)).into_affine())),
+            (Fq::from(0xdeadbeefu64), vec![]),
+            (-Fq::one(), vec![Fp::from(1u64), Fp::from(2u64), Fp::from(3u64)]),
+        ];
+        let actual: Vec<String> = vectors
+            .iter()
+            .map(|(k, m)| {
+                let (r, s) = signer.sign(*k, m.clone());
+                format!("{} {}", field_hex(&r), field_hex(&s))
+            })
+            .collect();
+        let expected: Vec<_> = include_str!("schnorr_vectors.txt").lines().collect();
+        if std::env::var("BBA_UPDATE_VECTORS").is_ok() {
+            let path = concat!(env!("CARGO_MANIFEST_DIR"), "/src/schnorr_vectors.txt");
+            std::fs::write(path, actual.join("\n") + "\n").unwrap();
+        } else {
+            assert!(
+                !expected.is_empty(),
+                "No Schnorr test vectors recorded, run with BBA_UPDATE_VECTORS=1"
+            );
+            assert_eq!(expected, actual, "Schnorr signatures changed");
+        }
     }
 
     let other_srs = SRS::<Other>::create(1 << ceil_log2(bba::MAX_COUNTERS));
@@ -62,6 +106,63 @@ fn main() {
     let proof_system_constants = fp_constants();
     let fq_proof_system_constants = fq_constants();
 
+    {
+        // The in-circuit sponge agrees with the native one, including across
+        // several permutations and interleaved absorbs and squeezes
+        let params = &proof_system_constants.poseidon;
+        let inputs: Vec<Fp> = (0..11).map(|i| Fp::from(i as u64)).collect();
+
+        let mut native = random_oracle::Sponge::new();
+        native.absorb(params, &inputs[..6]);
+        let mut expected = vec![native.squeeze(params)];
+        native.absorb(params, &inputs[6..]);
+        for _ in 0..5 {
+            expected.push(native.squeeze(params));
+        }
+
+        let mut gen = WitnessGenerator::new();
+        let vars: Vec<_> = inputs.iter().map(|x| gen.var(|| *x)).collect();
+        let mut sponge = gen.sponge();
+        gen.absorb(&proof_system_constants, &mut sponge, &vars[..6]);
+        let mut actual = vec![gen.squeeze(&proof_system_constants, &mut sponge)];
+        gen.absorb(&proof_system_constants, &mut sponge, &vars[6..]);
+        for _ in 0..5 {
+            actual.push(gen.squeeze(&proof_system_constants, &mut sponge));
+        }
+
+        let actual: Vec<_> = actual.iter().map(|v| v.val()).collect();
+        assert_eq!(expected, actual);
+    }
+
+    {
+        // Equalities cost no rows: the two public inputs end up in one permutation cycle
+        let sys = circuit_system::<Fp, _>(2, |sys, p| sys.assert_eq(p[0], p[1]));
+        assert_eq!(sys.gates.len(), 2);
+        let gates = sys.gates();
+        assert_eq!((gates[0].wires[0].row, gates[0].wires[0].col), (1, 0));
+        assert_eq!((gates[1].wires[0].row, gates[1].wires[0].col), (0, 0));
+
+        let one = Fp::one();
+        let assert_eq_public = |sys: &mut Checker<Fp>, p: Vec<Var<Fp>>| sys.assert_eq(p[0], p[1]);
+        assert!(check(&proof_system_constants, vec![one, one], assert_eq_public).is_ok());
+        assert!(check(&proof_system_constants, vec![one, one.double()], assert_eq_public).is_err());
+    }
+
+    {
+        // Constants are pinned once, and operations on them are folded natively
+        let sys = circuit_system::<Fp, _>(0, |sys, _| {
+            let two = sys.constant(Fp::one().double());
+            assert_eq!(two, sys.constant(Fp::one().double()));
+            let four = sys.scale(Fp::one().double(), two);
+            assert_eq!(sys.constant_value(four), Some(Fp::from(4u64)));
+            let (x, y) = proof_system_constants.base;
+            let base = (sys.constant(x), sys.constant(y));
+            let doubled = sys.double(base);
+            assert!(sys.constant_point(doubled).is_some());
+        });
+        assert_eq!(sys.gates.len(), 6);
+    }
+
     {
         let args : Vec<_> = std::env::args().collect();
         if args.len() < 3 {
@@ -99,7 +200,15 @@ fn main() {
             &proof_system_constants,
             &fq_poseidon,
             2,
-            |sys, p| bba_init_proof::circuit::<_, Other, _>(&init_params, &None, sys, p),
+            |sys, p| {
+                bba_init_proof::circuit::<_, Other, _>(
+                    &proof_system_constants,
+                    &init_params,
+                    &None,
+                    sys,
+                    p,
+                )
+            },
         );
         let init_vk = init_pk.verifier_index();
 
@@ -120,15 +229,50 @@ fn main() {
         );
         let update_vk = update_pk.verifier_index();
 
-        let open_params = bba_open_proof::Params {
-            // The public vector of prices-per-view for the campaigns
-            prices: (0..bba::MAX_COUNTERS)
+        let open_params = bba_open_proof::Params::new(
+            // The public vector of prices-per-view for the campaigns, in a single unit
+            vec![(0..bba::MAX_COUNTERS)
                 .map(|i| {
                     let i = i as u32;
                     i * i + 1
                 })
-                .collect(),
-        };
+                .collect()],
+            // No per-category disclosure
+            vec![],
+        );
+
+        {
+            // Each price schedule and each category of a disclosure policy gets its
+            // own limb of the total value
+            let params = bba_open_proof::Params::new(
+                vec![open_params.prices()[0].clone(), vec![10; bba::MAX_COUNTERS]],
+                vec![vec![0, 1], vec![5]],
+            );
+            let mut counters = vec![0; bba::MAX_COUNTERS];
+            counters[0] = 3;
+            counters[1] = 4;
+            counters[5] = 2;
+            counters[7] = 1;
+            let total: Fq = params.total_value(&counters);
+            let rewards = vec![3 * 1 + 4 * 2 + 2 * 26 + 50, 10 * 10];
+            assert_eq!(params.unpack_total_value(total), Some((rewards, vec![7, 2])));
+            assert_eq!(Some(params.limbs(&counters)), params.unpack_total_value(total));
+            let fewer_categories =
+                bba_open_proof::Params::new(params.prices().to_vec(), vec![vec![0, 1]]);
+            assert_eq!(fewer_categories.unpack_total_value(total), None);
+
+            let w = bba_open_proof::Witness {
+                counters,
+                alpha: array_init(|_| Fq::rand(&mut rand_core::OsRng)),
+            };
+            let open = |total| {
+                check(&fq_proof_system_constants, vec![Fq::zero(), total], |sys, p| {
+                    bba_open_proof::circuit::<_, Affine, _>(&params, &Some(w.clone()), sys, p)
+                })
+            };
+            assert!(open(total).is_ok());
+            assert!(open(total + &Fq::one()).is_err());
+        }
 
         let open_pk = generate_proving_key::<FqInner, _>(
             &other_srs,
@@ -139,6 +283,66 @@ fn main() {
         );
         let open_vk = open_pk.verifier_index();
 
+        {
+            // A private update proves each of its deltas to be 0 or 1
+            let check_delta = |deltas| {
+                let w = bba_delta_proof::Witness {
+                    deltas,
+                    alpha: array_init(|_| Fq::rand(&mut rand_core::OsRng)),
+                };
+                check(&fq_proof_system_constants, vec![Fq::zero(); 2], |sys, p| {
+                    bba_delta_proof::circuit(&Some(w), sys, p)
+                })
+            };
+            let mut deltas = vec![0; bba::MAX_COUNTERS];
+            deltas[3] = 1;
+            assert!(check_delta(deltas.clone()).is_ok());
+            deltas[4] = 2;
+            assert!(check_delta(deltas).is_err());
+        }
+
+        let delta_pk = generate_proving_key::<FqInner, _>(
+            &other_srs,
+            &fq_proof_system_constants,
+            &fp_poseidon,
+            bba_delta_proof::PUBLIC_INPUT,
+            |sys, p| bba_delta_proof::circuit(&None, sys, p),
+        );
+        let delta_vk = delta_pk.verifier_index();
+
+        // Set BBA_CIRCUIT_STATS to a directory to get the row count of each scope and
+        // the gate layout of every circuit, e.g. to track circuit size in review
+        if let Ok(dir) = std::env::var("BBA_CIRCUIT_STATS") {
+            let init = circuit_system(2, |sys, p| {
+                bba_init_proof::circuit::<_, Other, _>(
+                    &proof_system_constants,
+                    &init_params,
+                    &None,
+                    sys,
+                    p,
+                )
+            });
+            let update = circuit_system(2, |sys, p| {
+                bba_update_proof::circuit::<_, Other, _>(
+                    &proof_system_constants,
+                    &update_params,
+                    &None,
+                    sys,
+                    p,
+                )
+            });
+            let open = circuit_system(2, |sys, p| {
+                bba_open_proof::circuit::<_, Affine, _>(&open_params, &None, sys, p)
+            });
+            write_circuit_stats(&dir, "init", &init);
+            write_circuit_stats(&dir, "update", &update);
+            let delta = circuit_system(bba_delta_proof::PUBLIC_INPUT, |sys, p| {
+                bba_delta_proof::circuit::<Fq, _>(&None, sys, p)
+            });
+            write_circuit_stats(&dir, "open", &open);
+            write_circuit_stats(&dir, "delta", &delta);
+        }
+
         let other_lgr_comms: Vec<PolyComm<Affine>> = fft::lagrange_commitments(&srs)
             .iter()
             .map(|g| PolyComm {
@@ -171,6 +375,9 @@ fn main() {
             big_other_lgr_comms,
             lgr_comms: bba.lagrange_commitments.clone(),
             update_vk,
+            log: std::sync::Mutex::new(transparency_log::Log::new(signer.sponge.clone())),
+            // Set BBA_LOG_EVENTS to write the authority's events to stderr as JSON lines
+            metrics: metrics::Metrics::new(std::env::var("BBA_LOG_EVENTS").is_ok()),
         };
 
         let user_config = bba::UserConfig {
@@ -181,6 +388,7 @@ fn main() {
                 group_map: group_map.clone(),
                 g_group_map: g_group_map.clone(),
                 open_pk,
+                delta_pk,
                 open_params: open_params.clone(),
                 proof_system_constants: proof_system_constants.clone(),
                 init_params: init_params.clone(),
@@ -206,6 +414,33 @@ fn main() {
         let mut user =
             bba::User::<FpInner>::init(user_config, init_secrets, init_signature).unwrap();
 
+        {
+            // The constraint checker accepts the witness for an update proof and rejects
+            // one built from a forged signature, before any proving happens
+            let randomized = bba.randomize(user.state.acc);
+            let (x, y) = randomized.result.to_coordinates().unwrap();
+            let check_update = |signature| {
+                let w = bba_update_proof::Witness {
+                    acc: user.state.acc,
+                    signature,
+                    r: randomized.witness.0,
+                };
+                check(&proof_system_constants, vec![x, y], |sys, p| {
+                    bba_update_proof::circuit::<_, Other, _>(
+                        &proof_system_constants,
+                        &update_params,
+                        &Some(w),
+                        sys,
+                        p,
+                    )
+                })
+            };
+            check_update(user.state.signature).unwrap();
+            let (r, s) = user.state.signature;
+            let forged = check_update((r, s + &<Other as AffineCurve>::ScalarField::one()));
+            assert!(forged.unwrap_err().scope.starts_with("signature"));
+        }
+
         // Then, the user can request to perform an update by incrementing views in some campaigns
         let updates = (0..updates_per_accumulator)
             .map(|i| bba::SingleUpdate {
@@ -214,7 +449,7 @@ fn main() {
             })
             .collect();
         let update_request = time(&*format!("User:      Create BBA update request [{} counters updated]", updates_per_accumulator), || {
-            user.request_update::<SpongeQ, SpongeR>(updates)
+            user.request_update::<SpongeQ, SpongeR>(updates).unwrap()
         });
 
         // and the authority can validate the unlinkable update request and provide an updated BBA
@@ -225,11 +460,80 @@ fn main() {
                 .clone()
         });
         time("User:      Process update response", || {
-            user.process_update_response(&update_request.updates, &resp)
+            user.process_update_response(&resp).unwrap()
         });
+        // A response is only accepted once, since processing it consumes its pending entry
+        assert!(user.process_update_response(&resp).is_err());
+        user.check_invariant();
+
+        {
+            // Concurrent requests fork the accumulator: once one of their responses is
+            // processed, the other is for a superseded accumulator and gets abandoned
+            let update = |i| vec![bba::SingleUpdate { campaign_index: i, delta: 1 }];
+            let first = user.request_update::<SpongeQ, SpongeR>(update(0)).unwrap();
+            let second = user.request_update::<SpongeQ, SpongeR>(update(1)).unwrap();
+            let second_acc = second.randomized_acc();
+            let resps: Vec<_> = update_authority
+                .perform_updates::<SpongeQ, SpongeR>(vec![first, second])
+                .into_iter()
+                .map(|r| r.unwrap())
+                .collect();
+            assert!(resps[1].randomized_acc() == second_acc);
+            user.process_update_response(&resps[0]).unwrap();
+            assert!(user.process_update_response(&resps[1]).is_err());
+            assert!(user.abandon_update(&second_acc));
+            assert!(!user.abandon_update(&second_acc));
+            assert!(user.state.pending_updates.is_empty());
+            user.check_invariant();
+        }
+
+        {
+            // A private update adds one to each of its campaigns without revealing them,
+            // and refuses deltas above one
+            let update = |i, delta| bba::SingleUpdate { campaign_index: i, delta };
+            let counters = user.state.counters.clone();
+            let req = user
+                .request_private_update::<SpongeQ, SpongeR, PSpongeQ, PSpongeR>(vec![update(2, 1), update(5, 1)])
+                .unwrap();
+            assert!(req.update.updates.is_empty());
+            let resp = update_authority
+                .perform_private_updates::<SpongeQ, SpongeR, PSpongeQ, PSpongeR>(&g_group_map, &delta_vk, vec![req])[0]
+                .as_ref()
+                .unwrap()
+                .clone();
+            user.process_update_response(&resp).unwrap();
+            assert_eq!(user.state.counters[2], counters[2] + 1);
+            assert_eq!(user.state.counters[5], counters[5] + 1);
+            user.check_invariant();
+
+            let mut refused = |updates| {
+                user.request_private_update::<SpongeQ, SpongeR, PSpongeQ, PSpongeR>(updates).is_err()
+            };
+            assert!(refused(vec![update(2, 2)]));
+            assert!(refused(vec![update(2, 1), update(2, 1)]));
+            assert!(user.state.pending_updates.is_empty());
+        }
+
+        {
+            // The updated accumulator is in the authority's log, and the log at init time
+            // is a prefix of the current one
+            let head = update_authority.signed_tree_head();
+            let log = update_authority.log.lock().unwrap();
+            let entry = transparency_log::Entry::Accumulator(user.state.acc, user.state.signature);
+            let index = log.index_of(&entry).unwrap();
+            assert!(user.verify_logged(&head, index, &log.inclusion_proof(index, head.size)));
+            assert!(!user.verify_logged(&head, index, &log.inclusion_proof(0, head.size)));
+
+            let old_size = accumulators_to_update;
+            let proof = log.consistency_proof(old_size, head.size);
+            assert!(transparency_log::verify_consistency(
+                &signer.sponge, old_size, head.size, log.root(old_size), head.root, &proof));
+            assert!(!transparency_log::verify_consistency(
+                &signer.sponge, old_size, head.size, log.root(old_size - 1), head.root, &proof));
+        }
 
         // Now, the user can open their BBA to a reward in a zero-knowledge way
-        let opening = time("User:      Open BBA", || user.open::<PSpongeQ, PSpongeR>());
+        let opening = time("User:      Open BBA", || user.open::<PSpongeQ, PSpongeR>().unwrap());
         let opening_size = bba::proof_size(&opening.proof);
         // Finally, we can verify the correctness of the opening
 
@@ -238,6 +542,33 @@ fn main() {
                 &signer, &bba, brave_pubkey, &g_group_map, &open_vk, vec![&opening; accumulators_to_update])
         }).unwrap();
 
+        // The authority pays out the opening and returns a signed receipt, which it also
+        // appends to its receipt log. Once the user accepts it the wallet no longer opens.
+        let payout = update_authority
+            .verify_openings::<FpInner, PSpongeQ, PSpongeR>(&bba, &g_group_map, &open_vk, &open_params, vec![&opening])
+            .unwrap()
+            .remove(0);
+        assert!(
+            opening
+                .verify::<PSpongeQ, PSpongeR>(&signer, &bba, brave_pubkey, &g_group_map, &open_vk, &open_params)
+                .unwrap()
+                .amounts
+                == payout.amounts
+        );
+        let receipt = update_authority.issue_receipt(&payout).unwrap();
+        // A nullifier is only redeemed once
+        assert!(update_authority.issue_receipt(&payout).is_err());
+        assert!(bba::RedemptionReceipt::from_log_line(&receipt.log_line()) == Some(receipt.clone()));
+        let no_amounts = bba::RedemptionReceipt { amounts: vec![], ..receipt.clone() };
+        assert!(bba::RedemptionReceipt::from_log_line(&no_amounts.log_line()) == Some(no_amounts));
+        // The wallet only accepts the receipt as signed
+        let short_receipt = bba::RedemptionReceipt { amounts: vec![0], ..receipt.clone() };
+        assert!(user.accept_receipt(&short_receipt).is_err());
+        user.accept_receipt(&receipt).unwrap();
+        assert!(user.restore().is_err());
+        assert!(user.open::<PSpongeQ, PSpongeR>().is_err());
+        assert!(user.request_update::<SpongeQ, SpongeR>(vec![]).is_err());
+
         println!("------------------------------");
         println!(
             "Init proof size:    {} bytes",
@@ -248,5 +579,17 @@ fn main() {
             bba::proof_size(&update_request.proof)
         );
         println!("Opening proof size: {} bytes", opening_size);
+
+        {
+            let exported = update_authority.metrics.prometheus();
+            assert!(exported.contains("bba_proofs_total{kind=\"open\",result=\"verified\"}"));
+            assert!(exported.contains("bba_signatures_total{kind=\"receipt\"} 1"));
+        }
+
+        // Set BBA_METRICS to print the authority's metrics in the Prometheus text format
+        if std::env::var("BBA_METRICS").is_ok() {
+            println!("------------------------------");
+            print!("{}", update_authority.metrics.prometheus());
+        }
     }
 }
@@ -0,0 +1,199 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// Counters and histograms for the authority, exported in the Prometheus text format,
+// and structured log events written to stderr as one JSON object per line.
+
+pub const LATENCY_BUCKETS: [f64; 10] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0, 5.0];
+pub const BATCH_SIZE_BUCKETS: [f64; 8] = [1.0, 2.0, 5.0, 10.0, 50.0, 100.0, 500.0, 1000.0];
+
+type Labels = Vec<(&'static str, String)>;
+
+struct Histogram {
+    bounds: &'static [f64],
+    // counts[i] is the number of observations at most bounds[i] and above the
+    // previous bound; the last entry counts the ones above every bound
+    counts: Vec<u64>,
+    sum: f64,
+}
+
+#[derive(Default)]
+struct Registry {
+    counters: BTreeMap<(&'static str, Labels), u64>,
+    histograms: BTreeMap<(&'static str, Labels), Histogram>,
+}
+
+pub struct Metrics {
+    registry: Mutex<Registry>,
+    log_events: bool,
+}
+
+fn labels(ls: &[(&'static str, &str)]) -> Labels {
+    ls.iter().map(|(k, v)| (*k, v.to_string())).collect()
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn label_string(ls: &Labels, extra: Option<(&str, String)>) -> String {
+    let mut parts: Vec<_> = ls
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, escape(v)))
+        .collect();
+    if let Some((k, v)) = extra {
+        parts.push(format!("{}=\"{}\"", k, v));
+    }
+    if parts.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", parts.join(","))
+    }
+}
+
+impl Metrics {
+    // [log_events] controls whether [event] writes anything
+    pub fn new(log_events: bool) -> Self {
+        Metrics {
+            registry: Mutex::new(Registry::default()),
+            log_events,
+        }
+    }
+
+    pub fn inc(&self, name: &'static str, ls: &[(&'static str, &str)], by: u64) {
+        let mut registry = self.registry.lock().unwrap();
+        *registry.counters.entry((name, labels(ls))).or_insert(0) += by;
+    }
+
+    pub fn observe(
+        &self,
+        name: &'static str,
+        bounds: &'static [f64],
+        ls: &[(&'static str, &str)],
+        value: f64,
+    ) {
+        let mut registry = self.registry.lock().unwrap();
+        let h = registry
+            .histograms
+            .entry((name, labels(ls)))
+            .or_insert_with(|| Histogram {
+                bounds,
+                counts: vec![0; bounds.len() + 1],
+                sum: 0.0,
+            });
+        let i = h.bounds.iter().position(|b| value <= *b).unwrap_or(h.bounds.len());
+        h.counts[i] += 1;
+        h.sum += value;
+    }
+
+    pub fn observe_latency(&self, name: &'static str, ls: &[(&'static str, &str)], d: Duration) {
+        self.observe(name, &LATENCY_BUCKETS, ls, d.as_secs_f64())
+    }
+
+    // Writes {"event": name, field: value, ..} to stderr. Values are written as
+    // JSON strings.
+    pub fn event(&self, name: &str, fields: &[(&str, String)]) {
+        if !self.log_events {
+            return;
+        }
+        let mut line = format!("{{\"event\":\"{}\"", escape(name));
+        for (k, v) in fields.iter() {
+            write!(line, ",\"{}\":\"{}\"", escape(k), escape(v)).unwrap();
+        }
+        line.push('}');
+        eprintln!("{}", line);
+    }
+
+    // Records the verification of a batch of [batch_size] proofs of [kind] (init,
+    // update or open), taking [elapsed]
+    pub fn verified_batch(&self, kind: &'static str, batch_size: usize, elapsed: Duration) {
+        self.observe("bba_batch_size", &BATCH_SIZE_BUCKETS, &[("kind", kind)], batch_size as f64);
+        self.observe_latency("bba_verification_seconds", &[("kind", kind)], elapsed);
+        self.event(
+            "verify_batch",
+            &[
+                ("kind", kind.to_string()),
+                ("batch_size", batch_size.to_string()),
+                ("seconds", elapsed.as_secs_f64().to_string()),
+            ],
+        );
+    }
+
+    // Counts [n] proofs of [kind] which were accepted, or rejected for [reason]
+    pub fn proofs(&self, kind: &'static str, result: Result<(), &str>, n: usize) {
+        let ls = match result {
+            Ok(()) => vec![("kind", kind), ("result", "verified")],
+            Err(reason) => vec![("kind", kind), ("result", "rejected"), ("reason", reason)],
+        };
+        self.inc("bba_proofs_total", &ls, n as u64);
+        if let Err(reason) = result {
+            self.event(
+                "proof_rejected",
+                &[
+                    ("kind", kind.to_string()),
+                    ("reason", reason.to_string()),
+                    ("count", n.to_string()),
+                ],
+            );
+        }
+    }
+
+    // Counts a request of [kind] rejected for [reason] before its proof was
+    // verified, e.g. for an accumulator which is not a curve point
+    pub fn rejected_request(&self, kind: &'static str, reason: &str) {
+        self.inc("bba_requests_rejected_total", &[("kind", kind), ("reason", reason)], 1);
+        self.event(
+            "request_rejected",
+            &[("kind", kind.to_string()), ("reason", reason.to_string())],
+        );
+    }
+
+    // Records [n] signatures of [kind] (init, update, receipt or tree_head), taking
+    // [elapsed] in total. The signing rate is the rate of bba_signatures_total.
+    pub fn signed(&self, kind: &'static str, n: usize, elapsed: Duration) {
+        self.inc("bba_signatures_total", &[("kind", kind)], n as u64);
+        self.observe_latency("bba_signing_seconds", &[("kind", kind)], elapsed);
+        self.event(
+            "signed",
+            &[
+                ("kind", kind.to_string()),
+                ("count", n.to_string()),
+                ("seconds", elapsed.as_secs_f64().to_string()),
+            ],
+        );
+    }
+
+    pub fn prometheus(&self) -> String {
+        let registry = self.registry.lock().unwrap();
+        let mut out = String::new();
+        let mut last = None;
+        for ((name, ls), v) in registry.counters.iter() {
+            if last != Some(*name) {
+                writeln!(out, "# TYPE {} counter", name).unwrap();
+                last = Some(*name);
+            }
+            writeln!(out, "{}{} {}", name, label_string(ls, None), v).unwrap();
+        }
+        for ((name, ls), h) in registry.histograms.iter() {
+            if last != Some(*name) {
+                writeln!(out, "# TYPE {} histogram", name).unwrap();
+                last = Some(*name);
+            }
+            let mut cumulative = 0;
+            for (i, count) in h.counts.iter().enumerate() {
+                cumulative += count;
+                let le = match h.bounds.get(i) {
+                    Some(b) => b.to_string(),
+                    None => "+Inf".to_string(),
+                };
+                let bucket_labels = label_string(ls, Some(("le", le)));
+                writeln!(out, "{}_bucket{} {}", name, bucket_labels, cumulative).unwrap();
+            }
+            writeln!(out, "{}_sum{} {}", name, label_string(ls, None), h.sum).unwrap();
+            writeln!(out, "{}_count{} {}", name, label_string(ls, None), cumulative).unwrap();
+        }
+        out
+    }
+}
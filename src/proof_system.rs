@@ -1,4 +1,7 @@
-use crate::random_oracle;
+use crate::{
+    random_oracle::{self, SpongeState},
+    util::field_hex,
+};
 use algebra::{
     pasta::{fp::Fp, fq::Fq, pallas::Affine as Other, vesta::Affine},
     AffineCurve, BigInteger, FftField, Field, One, PrimeField, ProjectiveCurve, SquareRootField,
@@ -16,7 +19,12 @@ use plonk_5_wires_circuits::{
     wires::Wire,
 };
 use plonk_5_wires_protocol_dlog::{index::Index, plonk_sponge::FrSponge, prover::ProverProof};
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    marker::PhantomData,
+    panic::Location,
+};
 
 pub const COLUMNS: usize = 5;
 pub const ZK_ROWS: usize = 5;
@@ -128,22 +136,110 @@ pub struct Constants<F: Field> {
 
 pub struct System<F: FftField> {
     pub next_variable: usize,
-    // pub equivalence_classes: HashMap<Var, Vec<Position>>,
     pub gates: Vec<GateSpec<F>>,
+    // Union-find over variable indices. Variables in the same class share a
+    // permutation cycle in [System::gates].
+    pub equivalences: HashMap<usize, usize>,
+    // The stack of scopes entered with [Cs::scope], and the dotted scope path
+    // each gate was emitted in
+    pub scopes: Vec<String>,
+    pub gate_scopes: Vec<String>,
+    pub known_constants: KnownConstants<F>,
 }
 
 pub struct WitnessGenerator<F> {
+    pub next_variable: usize,
     pub rows: Vec<Row<F>>,
+    pub known_constants: KnownConstants<F>,
+}
+
+// The constants allocated with [Cs::constant]. Each value is pinned by a single row
+// however often it is used, and gadgets can recognize constant operands and compute
+// with them natively instead of emitting rows.
+pub struct KnownConstants<F> {
+    vars: HashMap<F, Var<F>>,
+    values: HashMap<usize, F>,
+}
+
+impl<F: Field> KnownConstants<F> {
+    pub fn new() -> Self {
+        KnownConstants {
+            vars: HashMap::new(),
+            values: HashMap::new(),
+        }
+    }
 }
 
 type Row<V> = [V; COLUMNS];
 
+// Marks bits which nothing constrains to be boolean yet
+pub struct Unconstrained;
+// Marks bits which are constrained to be boolean, either explicitly or by a
+// gate which enforces it on its inputs
+pub struct Boolean;
+
+// The bits of a scalar, least significant first. Only boolean-constrained bits
+// can be read, so unconstrained bits must go through a gadget which enforces
+// booleanity before they can be used elsewhere.
+pub struct Bits<F, S> {
+    vars: Vec<Var<F>>,
+    state: PhantomData<S>,
+}
+
+impl<F, S> Bits<F, S> {
+    fn new(vars: Vec<Var<F>>) -> Self {
+        Bits {
+            vars,
+            state: PhantomData,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.vars.len()
+    }
+}
+
+impl<F> Bits<F, Unconstrained> {
+    pub fn unconstrained(vars: Vec<Var<F>>) -> Self {
+        Bits::new(vars)
+    }
+}
+
+impl<F> Bits<F, Boolean> {
+    pub fn vars(&self) -> &[Var<F>] {
+        &self.vars
+    }
+}
+
+impl<F> std::ops::Index<usize> for Bits<F, Boolean> {
+    type Output = Var<F>;
+
+    fn index(&self, i: usize) -> &Var<F> {
+        &self.vars[i]
+    }
+}
+
+// The in-circuit counterpart of random_oracle::Sponge. Like the native sponge
+// it does no padding, so callers hashing inputs of varying length should absorb
+// the length first (see schnorr::hash_domain).
+#[derive(Clone)]
+pub struct SpongeVar<F> {
+    state: Row<Var<F>>,
+    // Whether each cell still holds the zero the sponge was initialized with
+    fresh: [bool; COLUMNS],
+    mode: SpongeState,
+}
+
 pub trait Cs<F: FftField> {
     fn var<G>(&mut self, g: G) -> Var<F>
     where
         G: FnOnce() -> F;
 
-    fn scalar<G, N: BigInteger>(&mut self, length: usize, g: G) -> Vec<Var<F>>
+    // Allocates the little-endian bits of a scalar. Nothing constrains them yet,
+    // so they can only be used by passing them to a gadget which enforces
+    // booleanity or to [assert_boolean].
+    #[track_caller]
+    fn scalar<G, N: BigInteger>(&mut self, length: usize, g: G) -> Bits<F, Unconstrained>
     where
         G: FnOnce() -> N,
     {
@@ -164,35 +260,59 @@ pub trait Cs<F: FftField> {
                 None => panic!(),
             }))
         }
-        res
+        Bits::new(res)
+    }
+
+    #[track_caller]
+    fn assert_boolean<S>(&mut self, bits: Bits<F, S>) -> Bits<F, Boolean> {
+        for &b in bits.vars.iter() {
+            let mut row = [self.var(|| F::zero()); COLUMNS];
+            row[0] = b;
+            row[1] = b;
+            let mut c = vec![F::zero(); COLUMNS + 2];
+            c[0] = F::one();
+            c[COLUMNS] = -F::one();
+            self.gate(GateSpec {
+                typ: GateType::Generic,
+                row,
+                c,
+            });
+        }
+        Bits::new(bits.vars)
     }
 
     fn gate(&mut self, g: GateSpec<F>);
 
-    // TODO: Optimize to use permutation argument.
-    fn assert_eq(&mut self, x1: Var<F>, x2: Var<F>) {
-        let row = array_init(|i| {
-            if i == 0 {
-                x1
-            } else if i == 1 {
-                x2
-            } else {
-                self.var(|| F::zero())
-            }
-        });
+    fn known_constants(&mut self) -> &mut KnownConstants<F>;
 
-        let mut c = vec![F::zero(); COLUMNS + 2];
-        c[0] = F::one();
-        c[1] = -F::one();
+    // The value of [v] if it was allocated with [constant]
+    fn constant_value(&mut self, v: Var<F>) -> Option<F> {
+        self.known_constants().values.get(&v.index).copied()
+    }
 
-        self.gate(GateSpec {
-            typ: GateType::Generic,
-            row,
-            c,
-        });
+    fn constant_point(&mut self, (x, y): (Var<F>, Var<F>)) -> Option<(F, F)> {
+        Some((self.constant_value(x)?, self.constant_value(y)?))
+    }
+
+    // Attributes the rows emitted by [f] to the scope [name], nested in any
+    // enclosing scope. Only [System] and [Checker] record scopes.
+    fn scope<A, H>(&mut self, _name: &str, f: H) -> A
+    where
+        H: FnOnce(&mut Self) -> A,
+    {
+        f(self)
     }
 
+    // Constrains two variables to be equal. This is enforced by the permutation
+    // argument and costs no rows.
+    fn assert_eq(&mut self, x1: Var<F>, x2: Var<F>);
+
+    // Constants are cached, so allocating the same value twice costs one row.
+    #[track_caller]
     fn constant(&mut self, x: F) -> Var<F> {
+        if let Some(v) = self.known_constants().vars.get(&x) {
+            return *v;
+        }
         let v = self.var(|| x);
 
         let mut c = vec![F::zero(); COLUMNS + 2];
@@ -212,10 +332,18 @@ pub trait Cs<F: FftField> {
             row,
             c,
         });
+
+        let known = self.known_constants();
+        known.vars.insert(x, v);
+        known.values.insert(v.index, x);
         v
     }
 
+    #[track_caller]
     fn scale(&mut self, x: F, v: Var<F>) -> Var<F> {
+        if let Some(c) = self.constant_value(v) {
+            return self.constant(x * &c);
+        }
         let xv = self.var(|| v.val() * x);
         let row = [
             v,
@@ -235,7 +363,14 @@ pub trait Cs<F: FftField> {
         xv
     }
 
+    #[track_caller]
     fn double(&mut self, (x1, y1): (Var<F>, Var<F>)) -> (Var<F>, Var<F>) {
+        if let Some(p) = self.constant_point((x1, y1)) {
+            // The Double gate would be unsatisfiable for this
+            assert!(!p.1.is_zero(), "double on a constant point with y = 0");
+            let (x2, y2) = add_points(p, p);
+            return (self.constant(x2), self.constant(y2));
+        }
         let y1_inv = self.var(|| y1.val().inverse().unwrap());
 
         let mut r = None;
@@ -256,6 +391,7 @@ pub trait Cs<F: FftField> {
         (x2, y2)
     }
 
+    #[track_caller]
     fn assert_add_group(&mut self, (x1, y1): (Var<F>, Var<F>), (x2, y2): (Var<F>, Var<F>), (x3, y3): (Var<F>, Var<F>)) {
         let inv = self.var(|| (x2.val() - x1.val()).inverse().unwrap());
         self.gate(GateSpec {
@@ -277,7 +413,17 @@ pub trait Cs<F: FftField> {
         });
     }
 
+    #[track_caller]
     fn add_group(&mut self, (x1, y1): (Var<F>, Var<F>), (x2, y2): (Var<F>, Var<F>)) -> (Var<F>, Var<F>) {
+        if let (Some(p), Some(q)) = (
+            self.constant_point((x1, y1)),
+            self.constant_point((x2, y2)),
+        ) {
+            // The Add gate would be unsatisfiable for these
+            assert!(p.0 != q.0, "add_group on constant points with equal x");
+            let (x3, y3) = add_points(p, q);
+            return (self.constant(x3), self.constant(y3));
+        }
         let mut r = None;
         let x3 = self.var(|| {
             let pq = add_points((x1.val(), y1.val()), (x2.val(), y2.val()));
@@ -293,6 +439,7 @@ pub trait Cs<F: FftField> {
         (x3, y3)
     }
 
+    #[track_caller]
     fn cond_select(&mut self, b: Var<F>, t: Var<F>, f: Var<F>) -> Var<F> {
         // Could maybe be more efficient
         // delta = t - f
@@ -324,7 +471,13 @@ pub trait Cs<F: FftField> {
         res
     }
 
-    fn scalar_mul(&mut self, (xt, yt): (Var<F>, Var<F>), bits: Vec<Var<F>>) -> (Var<F>, Var<F>) {
+    // Vbmul1 constrains every bit but the lowest to be boolean, and the lowest one
+    // is constrained explicitly. For a constant base the initial doubling and the
+    // negation are folded, but every bit still costs a Vbmul1 row and its Zero
+    // row, since the bits are variables.
+    #[track_caller]
+    fn scalar_mul<S>(&mut self, (xt, yt): (Var<F>, Var<F>), bits: Bits<F, S>) -> (Var<F>, Var<F>) {
+        let bits = bits.vars;
         let (mut xp, mut yp) = self.double((xt, yt));
         // xt yt s1 s2 b
         // xs ys xp yp _
@@ -394,19 +547,29 @@ pub trait Cs<F: FftField> {
         )
     }
 
-    fn endo(
+    // Endomul constrains both bits it consumes to be boolean.
+    #[track_caller]
+    fn endo<S>(
         &mut self,
         constants: &Constants<F>,
         (xt, yt): (Var<F>, Var<F>),
-        bits: Vec<Var<F>>,
+        bits: Bits<F, S>,
     ) -> (Var<F>, Var<F>) {
+        let bits = bits.vars;
         let endo = constants.endo;
 
-        // 2(phi(p) + p)
-        let (mut xp, mut yp) = {
-            let phip = (self.scale(endo, xt), yt);
-            let phip_p = self.add_group(phip, (xt, yt));
-            self.double(phip_p)
+        // 2(phi(p) + p), computed natively for a constant base
+        let (mut xp, mut yp) = match self.constant_point((xt, yt)) {
+            Some(t) => {
+                let phit_t = add_points((endo * &t.0, t.1), t);
+                let (x, y) = add_points(phit_t, phit_t);
+                (self.constant(x), self.constant(y))
+            }
+            None => {
+                let phip = (self.scale(endo, xt), yt);
+                let phip_p = self.add_group(phip, (xt, yt));
+                self.double(phip_p)
+            }
         };
 
         for i in (0..128).rev() {
@@ -446,7 +609,10 @@ pub trait Cs<F: FftField> {
         (xp, yp)
     }
 
-    fn assert_pack(&mut self, x: Var<F>, bits_lsb: &Vec<Var<F>>) {
+    // Pack constrains the bits it consumes to be boolean, so the bits are returned
+    // as such.
+    #[track_caller]
+    fn assert_pack<S>(&mut self, x: Var<F>, bits_lsb: Bits<F, S>) -> Bits<F, Boolean> {
         let z = self.constant(F::zero());
         let init = [z, z, z, z, z];
         self.gate(GateSpec {
@@ -456,7 +622,7 @@ pub trait Cs<F: FftField> {
         });
 
         assert_eq!(bits_lsb.len(), 256);
-        let mut bits_msb = bits_lsb.clone();
+        let mut bits_msb = bits_lsb.vars.clone();
         bits_msb.reverse();
         let mut acc = z;
         for k in 0..63 {
@@ -492,8 +658,10 @@ pub trait Cs<F: FftField> {
                 x,
             ],
         });
+        Bits::new(bits_lsb.vars)
     }
 
+    #[track_caller]
     fn zk(&mut self) {
         for _ in 0..ZK_ROWS {
             let row = array_init(|_| self.var(|| F::rand(&mut rand_core::OsRng)));
@@ -505,6 +673,7 @@ pub trait Cs<F: FftField> {
         }
     }
 
+    #[track_caller]
     fn poseidon(&mut self, constants: &Constants<F>, input: Row<Var<F>>) -> Row<Var<F>> {
         let params = &constants.poseidon;
         let res = (0..random_oracle::POSEIDON_ROUNDS).fold(input.clone(), |prev, round| {
@@ -540,6 +709,108 @@ pub trait Cs<F: FftField> {
         });
         res
     }
+
+    #[track_caller]
+    fn add(&mut self, x1: Var<F>, x2: Var<F>) -> Var<F> {
+        let res = self.var(|| x1.val() + &x2.val());
+        let row = [
+            x1,
+            x2,
+            res,
+            self.var(|| F::zero()),
+            self.var(|| F::zero()),
+        ];
+        let mut c = vec![F::zero(); COLUMNS + 2];
+        c[0] = F::one();
+        c[1] = F::one();
+        c[2] = -F::one();
+        self.gate(GateSpec {
+            typ: GateType::Generic,
+            row,
+            c,
+        });
+        res
+    }
+
+    fn sponge(&mut self) -> SpongeVar<F> {
+        let zero = self.constant(F::zero());
+        SpongeVar {
+            state: [zero; COLUMNS],
+            fresh: [true; COLUMNS],
+            mode: SpongeState::Absorbed(0),
+        }
+    }
+
+    #[track_caller]
+    fn permute(&mut self, constants: &Constants<F>, sponge: &mut SpongeVar<F>) {
+        sponge.state = self.poseidon(constants, sponge.state);
+        sponge.fresh = [false; COLUMNS];
+    }
+
+    #[track_caller]
+    fn absorb_one(&mut self, sponge: &mut SpongeVar<F>, i: usize, x: Var<F>) {
+        // Adding to a cell which still holds its initial zero costs no rows
+        sponge.state[i] = if sponge.fresh[i] {
+            x
+        } else {
+            self.add(sponge.state[i], x)
+        };
+        sponge.fresh[i] = false;
+    }
+
+    // Absorbs [xs] into [sponge] with the same semantics as random_oracle::Sponge::absorb.
+    #[track_caller]
+    fn absorb(&mut self, constants: &Constants<F>, sponge: &mut SpongeVar<F>, xs: &[Var<F>]) {
+        for &x in xs.iter() {
+            match sponge.mode {
+                SpongeState::Absorbed(n) => {
+                    if n == random_oracle::SPONGE_RATE {
+                        self.permute(constants, sponge);
+                        self.absorb_one(sponge, 0, x);
+                        sponge.mode = SpongeState::Absorbed(1);
+                    } else {
+                        self.absorb_one(sponge, n, x);
+                        sponge.mode = SpongeState::Absorbed(n + 1);
+                    }
+                }
+                SpongeState::Squeezed(_) => {
+                    self.absorb_one(sponge, 0, x);
+                    sponge.mode = SpongeState::Absorbed(1);
+                }
+            }
+        }
+    }
+
+    // Squeezes an element from [sponge] with the same semantics as
+    // random_oracle::Sponge::squeeze.
+    #[track_caller]
+    fn squeeze(&mut self, constants: &Constants<F>, sponge: &mut SpongeVar<F>) -> Var<F> {
+        match sponge.mode {
+            SpongeState::Squeezed(n) => {
+                if n == random_oracle::SPONGE_RATE {
+                    self.permute(constants, sponge);
+                    sponge.mode = SpongeState::Squeezed(1);
+                    sponge.state[0]
+                } else {
+                    sponge.mode = SpongeState::Squeezed(n + 1);
+                    sponge.state[n]
+                }
+            }
+            SpongeState::Absorbed(_) => {
+                self.permute(constants, sponge);
+                sponge.mode = SpongeState::Squeezed(1);
+                sponge.state[0]
+            }
+        }
+    }
+
+    // Absorbs [inputs] into a fresh sponge and squeezes a single element.
+    #[track_caller]
+    fn hash(&mut self, constants: &Constants<F>, inputs: &[Var<F>]) -> Var<F> {
+        let mut sponge = self.sponge();
+        self.absorb(constants, &mut sponge, inputs);
+        self.squeeze(constants, &mut sponge)
+    }
 }
 
 fn add_points<F: Field>(a: (F, F), b: (F, F)) -> (F, F) {
@@ -576,8 +847,10 @@ impl<F: FftField> Cs<F> for WitnessGenerator<F> {
     where
         G: FnOnce() -> F,
     {
+        let v = self.next_variable;
+        self.next_variable += 1;
         Var {
-            index: 0,
+            index: v,
             value: Some(g()),
         }
     }
@@ -585,9 +858,23 @@ impl<F: FftField> Cs<F> for WitnessGenerator<F> {
     fn gate(&mut self, g: GateSpec<F>) {
         self.rows.push(array_init(|i| g.row[i].value.unwrap()))
     }
+
+    fn known_constants(&mut self) -> &mut KnownConstants<F> {
+        &mut self.known_constants
+    }
+
+    fn assert_eq(&mut self, _x1: Var<F>, _x2: Var<F>) {}
 }
 
 impl<F: FftField> WitnessGenerator<F> {
+    pub fn new() -> Self {
+        WitnessGenerator {
+            next_variable: 0,
+            rows: vec![],
+            known_constants: KnownConstants::new(),
+        }
+    }
+
     fn columns(&self) -> [Vec<F>; COLUMNS] {
         array_init(|col| {
             let mut v: Vec<_> = self.rows.iter().map(|row| row[col]).collect();
@@ -611,10 +898,78 @@ impl<F: FftField> Cs<F> for System<F> {
 
     fn gate(&mut self, g: GateSpec<F>) {
         self.gates.push(g);
+        self.gate_scopes.push(self.scopes.join("."));
+    }
+
+    fn known_constants(&mut self) -> &mut KnownConstants<F> {
+        &mut self.known_constants
+    }
+
+    fn assert_eq(&mut self, x1: Var<F>, x2: Var<F>) {
+        let (r1, r2) = (self.class(x1.index), self.class(x2.index));
+        if r1 != r2 {
+            self.equivalences.insert(r1, r2);
+        }
+    }
+
+    fn scope<A, H>(&mut self, name: &str, f: H) -> A
+    where
+        H: FnOnce(&mut Self) -> A,
+    {
+        self.scopes.push(name.to_string());
+        let res = f(self);
+        self.scopes.pop();
+        res
     }
 }
 
 impl<F: FftField> System<F> {
+    pub fn new() -> Self {
+        System {
+            next_variable: 0,
+            gates: vec![],
+            equivalences: HashMap::new(),
+            scopes: vec![],
+            gate_scopes: vec![],
+            known_constants: KnownConstants::new(),
+        }
+    }
+
+    // The representative of the equivalence class of a variable
+    fn class(&self, mut v: usize) -> usize {
+        while let Some(&parent) = self.equivalences.get(&v) {
+            v = parent;
+        }
+        v
+    }
+
+    // The number of rows emitted in each scope, including the rows of its nested
+    // scopes. Rows emitted outside of any scope are counted under "".
+    pub fn row_counts(&self) -> BTreeMap<String, usize> {
+        let mut counts = BTreeMap::new();
+        for path in self.gate_scopes.iter() {
+            *counts.entry(String::new()).or_insert(0) += 1;
+            let mut prefix = String::new();
+            for name in path.split('.').filter(|n| !n.is_empty()) {
+                if !prefix.is_empty() {
+                    prefix.push('.');
+                }
+                prefix.push_str(name);
+                *counts.entry(prefix.clone()).or_insert(0) += 1;
+            }
+        }
+        counts
+    }
+
+    pub fn row_count_report(&self) -> String {
+        let mut res = String::new();
+        for (path, n) in self.row_counts() {
+            let name: &str = if path.is_empty() { "total" } else { &path };
+            res.push_str(&format!("{:<40} {}\n", name, n));
+        }
+        res
+    }
+
     pub fn gates(&self) -> Vec<CircuitGate<F>> {
         let mut first_cell: HashMap<usize, Wire> = HashMap::new();
         let mut most_recent_cell: HashMap<usize, Wire> = HashMap::new();
@@ -622,7 +977,7 @@ impl<F: FftField> System<F> {
 
         for (i, gs) in self.gates.iter().enumerate() {
             let wires = array_init(|j| -> Wire {
-                let v = gs.row[j].index;
+                let v = self.class(gs.row[j].index);
                 let curr = Wire { row: i, col: j };
                 match most_recent_cell.insert(v, curr) {
                     Some(w) => w,
@@ -648,6 +1003,329 @@ impl<F: FftField> System<F> {
 
         return gates;
     }
+
+    // One line per row: the gate type, the scope it was emitted in, the cell each
+    // wire points to in the permutation and the coefficients.
+    pub fn gates_csv(&self) -> String
+    where
+        F: PrimeField,
+    {
+        let mut res = String::from("row,typ,scope,w0,w1,w2,w3,w4,c\n");
+        for (g, scope) in self.gates().iter().zip(self.gate_scopes.iter()) {
+            let wires: Vec<_> = g
+                .wires
+                .iter()
+                .map(|w| format!("{}:{}", w.row, w.col))
+                .collect();
+            let c: Vec<_> = g.c.iter().map(field_hex).collect();
+            res.push_str(&format!(
+                "{},{:?},{},{},{}\n",
+                g.row,
+                g.typ,
+                scope,
+                wires.join(","),
+                c.join(" ")
+            ));
+        }
+        res
+    }
+
+    pub fn gates_json(&self) -> String
+    where
+        F: PrimeField,
+    {
+        let rows: Vec<_> = self
+            .gates()
+            .iter()
+            .zip(self.gate_scopes.iter())
+            .map(|(g, scope)| {
+                let wires: Vec<_> = g
+                    .wires
+                    .iter()
+                    .map(|w| format!("{{\"row\":{},\"col\":{}}}", w.row, w.col))
+                    .collect();
+                let c: Vec<_> = g.c.iter().map(|x| format!("\"{}\"", field_hex(x))).collect();
+                format!(
+                    "{{\"row\":{},\"typ\":\"{:?}\",\"scope\":\"{}\",\"wires\":[{}],\"c\":[{}]}}",
+                    g.row,
+                    g.typ,
+                    scope,
+                    wires.join(","),
+                    c.join(",")
+                )
+            })
+            .collect();
+        format!("[\n{}\n]\n", rows.join(",\n"))
+    }
+}
+
+// A constraint system which evaluates each gate against the witness as it is
+// generated, so that a broken witness is reported with the row and the code
+// which emitted the offending gate instead of as a failure in the prover.
+//
+// It checks the relations the gadgets compute their witnesses by (see
+// [satisfied]), not the gate polynomials of plonk_5_wires_circuits. A row it
+// accepts can still fail in the prover if a polynomial differs from the relation,
+// e.g. in how it handles the point at infinity, so this is a debugging aid rather
+// than a replacement for proving. Gate types the gadgets never emit make it panic.
+pub struct Checker<F: FftField> {
+    pub constants: Constants<F>,
+    pub next_variable: usize,
+    pub rows: Vec<CheckedRow<F>>,
+    pub failure: Option<Unsatisfied>,
+    pub known_constants: KnownConstants<F>,
+    scopes: Vec<String>,
+}
+
+pub struct CheckedRow<F> {
+    pub typ: GateType,
+    pub c: Vec<F>,
+    pub row: Row<F>,
+    pub location: &'static Location<'static>,
+    // The path of [Cs::scope]s the row was emitted in, as in [System::row_counts]
+    pub scope: String,
+}
+
+// The first constraint the witness violates. [typ] is None for an equality
+// asserted with [Cs::assert_eq], in which case [row] is the number of rows
+// emitted before it.
+#[derive(Debug, Clone)]
+pub struct Unsatisfied {
+    pub row: usize,
+    pub typ: Option<GateType>,
+    pub location: &'static Location<'static>,
+    pub scope: String,
+}
+
+impl fmt::Display for Unsatisfied {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.typ {
+            Some(typ) => write!(
+                f,
+                "row {} is not satisfied ({:?} gate emitted in scope \"{}\" at {})",
+                self.row, typ, self.scope, self.location
+            ),
+            None => write!(
+                f,
+                "equality asserted before row {} is not satisfied (in scope \"{}\" at {})",
+                self.row, self.scope, self.location
+            ),
+        }
+    }
+}
+
+impl<F: FftField> Cs<F> for Checker<F> {
+    fn var<G>(&mut self, g: G) -> Var<F>
+    where
+        G: FnOnce() -> F,
+    {
+        let v = self.next_variable;
+        self.next_variable += 1;
+        Var {
+            index: v,
+            value: Some(g()),
+        }
+    }
+
+    #[track_caller]
+    fn assert_eq(&mut self, x1: Var<F>, x2: Var<F>) {
+        if self.failure.is_none() && x1.val() != x2.val() {
+            self.failure = Some(Unsatisfied {
+                row: self.rows.len(),
+                typ: None,
+                location: Location::caller(),
+                scope: self.scopes.join("."),
+            });
+        }
+    }
+
+    #[track_caller]
+    fn gate(&mut self, g: GateSpec<F>) {
+        self.rows.push(CheckedRow {
+            typ: g.typ,
+            c: g.c,
+            row: array_init(|i| g.row[i].val()),
+            location: Location::caller(),
+            scope: self.scopes.join("."),
+        });
+        // Most gates also constrain the row after them, so a row is checked once
+        // its successor is known.
+        let n = self.rows.len();
+        if n > 1 {
+            self.check_row(n - 2);
+        }
+    }
+
+    fn known_constants(&mut self) -> &mut KnownConstants<F> {
+        &mut self.known_constants
+    }
+
+    fn scope<A, H>(&mut self, name: &str, f: H) -> A
+    where
+        H: FnOnce(&mut Self) -> A,
+    {
+        self.scopes.push(name.to_string());
+        let res = f(self);
+        self.scopes.pop();
+        res
+    }
+}
+
+impl<F: FftField> Checker<F> {
+    pub fn new(constants: &Constants<F>) -> Self {
+        Checker {
+            constants: constants.clone(),
+            next_variable: 0,
+            rows: vec![],
+            failure: None,
+            known_constants: KnownConstants::new(),
+            scopes: vec![],
+        }
+    }
+
+    fn check_row(&mut self, i: usize) {
+        if self.failure.is_some() {
+            return;
+        }
+        let zeros = [F::zero(); COLUMNS];
+        let next = self.rows.get(i + 1).map_or(&zeros, |r| &r.row);
+        let r = &self.rows[i];
+        if !satisfied(&self.constants, &r.typ, &r.c, &r.row, next) {
+            self.failure = Some(Unsatisfied {
+                row: i,
+                typ: Some(r.typ.clone()),
+                location: r.location,
+                scope: r.scope.clone(),
+            });
+        }
+    }
+
+    // Checks the last row, whose successor is the zero padding added by the prover,
+    // and returns the first unsatisfied row if any.
+    pub fn finish(mut self) -> Result<(), Unsatisfied> {
+        if !self.rows.is_empty() {
+            let n = self.rows.len();
+            self.check_row(n - 1);
+        }
+        match self.failure {
+            None => Ok(()),
+            Some(e) => Err(e),
+        }
+    }
+}
+
+fn is_bool<F: Field>(b: F) -> bool {
+    b.is_zero() || b.is_one()
+}
+
+// The step shared by Vbmul1 and Endomul: the next row holds S = (Q + P) + P along
+// with P, and s1, s2 are the slopes of the two additions.
+fn double_add_satisfied<F: Field>(q: (F, F), (s1, s2): (F, F), next: &Row<F>) -> bool {
+    let [xs, ys, xp, yp, _] = *next;
+    let p = (xp, yp);
+    (q.0 - &xp) * &s1 == q.1 - &yp
+        && (xp - &xs) * &s2 == ys + &yp
+        && add_points(add_points(q, p), p) == (xs, ys)
+}
+
+// Whether the row [w] with successor [next] satisfies a gate. These are the relations
+// the gadgets in [Cs] compute their witnesses by, rather than the gate polynomials
+// themselves.
+fn satisfied<F: FftField>(
+    constants: &Constants<F>,
+    typ: &GateType,
+    c: &[F],
+    w: &Row<F>,
+    next: &Row<F>,
+) -> bool {
+    match typ {
+        GateType::Zero => true,
+        GateType::Generic => {
+            let linear = (0..COLUMNS).fold(F::zero(), |acc, i| acc + &(c[i] * &w[i]));
+            (linear + &(c[COLUMNS] * &w[0] * &w[1]) + &c[COLUMNS + 1]).is_zero()
+        }
+        GateType::Add => {
+            let [x1, y1, x2, y2, inv] = *w;
+            (x2 - &x1) * &inv == F::one() && add_points((x1, y1), (x2, y2)) == (next[0], next[1])
+        }
+        GateType::Double => {
+            let [x1, y1, x2, y2, y1_inv] = *w;
+            y1 * &y1_inv == F::one() && add_points((x1, y1), (x1, y1)) == (x2, y2)
+        }
+        GateType::Vbmul1 => {
+            let [xt, yt, s1, s2, b] = *w;
+            let yq = (b.double() - &F::one()) * &yt;
+            is_bool(b) && double_add_satisfied((xt, yq), (s1, s2), next)
+        }
+        GateType::Endomul => {
+            let [xt, yt, s1, s2, b_2i] = *w;
+            let b_2i1 = next[4];
+            let xq = (F::one() + &((constants.endo - &F::one()) * &b_2i1)) * &xt;
+            let yq = (b_2i.double() - &F::one()) * &yt;
+            is_bool(b_2i) && is_bool(b_2i1) && double_add_satisfied((xq, yq), (s1, s2), next)
+        }
+        GateType::Pack => {
+            let sixteen: F = (16 as u64).into();
+            let eight: F = (8 as u64).into();
+            let four: F = (4 as u64).into();
+            next[..4].iter().all(|b| is_bool(*b))
+                && next[4]
+                    == sixteen * &w[4]
+                        + &(eight * &next[0])
+                        + &(four * &next[1])
+                        + &next[2].double()
+                        + &next[3]
+        }
+        GateType::Poseidon => {
+            let params = &constants.poseidon;
+            let s: Row<F> = array_init(|j| sbox::<F, PlonkSpongeConstants>(w[j]));
+            (0..COLUMNS).all(|i| {
+                let m = &params.mds[i];
+                next[i]
+                    == c[i]
+                        + &s.iter()
+                            .zip(m.iter())
+                            .fold(F::zero(), |x, (s, &m)| m * s + x)
+            })
+        }
+        // The gadgets do not emit any other gate types, so a circuit with one was
+        // not built by them and cannot be checked
+        _ => panic!("no relation to check {:?} gates against", typ),
+    }
+}
+
+// Runs [main] against the constraint checker with the same public input layout as
+// [prove] and returns the first unsatisfied row, if any.
+pub fn check<F: FftField, H>(
+    constants: &Constants<F>,
+    public_input: Vec<F>,
+    main: H,
+) -> Result<(), Unsatisfied>
+where
+    H: FnOnce(&mut Checker<F>, Vec<Var<F>>) -> (),
+{
+    let mut checker = Checker::new(constants);
+
+    let public_input: Vec<_> = public_input
+        .iter()
+        .map(|x| {
+            let v = checker.var(|| *x);
+            let row = array_init(|i| if i == 0 { v } else { checker.var(|| F::zero()) });
+            // The verifier contributes -x for each public input
+            let mut c = vec![F::zero(); COLUMNS + 2];
+            c[0] = F::one();
+            c[COLUMNS + 1] = -*x;
+            checker.gate(GateSpec {
+                typ: GateType::Generic,
+                c,
+                row,
+            });
+            v
+        })
+        .collect();
+
+    main(&mut checker, public_input);
+    checker.finish()
 }
 
 pub fn prove<
@@ -666,23 +1344,14 @@ pub fn prove<
 where
     H: FnOnce(&mut WitnessGenerator<G::ScalarField>, Vec<Var<G::ScalarField>>) -> (),
 {
-    let mut gen: WitnessGenerator<G::ScalarField> = WitnessGenerator {
-        rows: public_input
-            .iter()
-            .map(|x| array_init(|i| if i == 0 { *x } else { G::ScalarField::zero() }))
-            .collect(),
-    };
+    let mut gen: WitnessGenerator<G::ScalarField> = WitnessGenerator::new();
+    gen.rows = public_input
+        .iter()
+        .map(|x| array_init(|i| if i == 0 { *x } else { G::ScalarField::zero() }))
+        .collect();
 
-    main(
-        &mut gen,
-        public_input
-            .iter()
-            .map(|x| Var {
-                index: 0,
-                value: Some(*x),
-            })
-            .collect(),
-    );
+    let public_input = public_input.iter().map(|x| gen.var(|| *x)).collect();
+    main(&mut gen, public_input);
 
     let columns = gen.columns();
 
@@ -700,6 +1369,40 @@ where
         .unwrap()
 }
 
+// The constraint system of a circuit with [public] public inputs, without a witness.
+pub fn circuit_system<F: FftField, H>(public: usize, main: H) -> System<F>
+where
+    H: FnOnce(&mut System<F>, Vec<Var<F>>) -> (),
+{
+    let mut system: System<F> = System::new();
+    let z = F::zero();
+    let public_input_row = vec![F::one(), z, z, z, z, z, z];
+
+    let public_input: Vec<_> = system.scope("public_input", |system| {
+        (0..public)
+            .map(|_| {
+                let v = system.var(|| panic!("fail"));
+                let row = array_init(|i| {
+                    if i == 0 {
+                        v.clone()
+                    } else {
+                        system.var(|| panic!("fail"))
+                    }
+                });
+                system.gate(GateSpec {
+                    typ: GateType::Generic,
+                    c: public_input_row.clone(),
+                    row,
+                });
+                v
+            })
+            .collect()
+    });
+
+    main(&mut system, public_input);
+    system
+}
+
 pub fn generate_proving_key<'a, C: Cycle, H>(
     srs: &'a SRS<C::Outer>,
     constants: &Constants<C::InnerField>,
@@ -710,35 +1413,7 @@ pub fn generate_proving_key<'a, C: Cycle, H>(
 where
     H: FnOnce(&mut System<C::InnerField>, Vec<Var<C::InnerField>>) -> (),
 {
-    let mut system: System<C::InnerField> = System {
-        next_variable: 0,
-        gates: vec![],
-    };
-    let z = C::InnerField::zero();
-    let public_input_row = vec![C::InnerField::one(), z, z, z, z, z, z];
-
-    let public_input: Vec<_> = (0..public)
-        .map(|_| {
-            let v = system.var(|| panic!("fail"));
-            let row = array_init(|i| {
-                if i == 0 {
-                    v.clone()
-                } else {
-                    system.var(|| panic!("fail"))
-                }
-            });
-            system.gate(GateSpec {
-                typ: GateType::Generic,
-                c: public_input_row.clone(),
-                row,
-            });
-            v
-        })
-        .collect();
-
-    main(&mut system, public_input);
-
-    let gates = system.gates();
+    let gates = circuit_system(public, main).gates();
     // Other base field = self scalar field
     let (endo_q, _endo_r) = endos::<C::Inner>();
     Index::<C::Outer>::create(
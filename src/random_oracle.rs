@@ -1 +1,91 @@
+use algebra::Field;
+use array_init::array_init;
+use oracle::{poseidon::ArithmeticSpongeParams, poseidon_5_wires::*};
+
 pub const POSEIDON_ROUNDS: usize = 31;
+
+pub const SPONGE_WIDTH: usize = 5;
+pub const SPONGE_RATE: usize = SPONGE_WIDTH - 1;
+
+// The permutation computed by the Poseidon gate, applied POSEIDON_ROUNDS times.
+pub fn permute<F: Field>(
+    params: &ArithmeticSpongeParams<F>,
+    input: [F; SPONGE_WIDTH],
+) -> [F; SPONGE_WIDTH] {
+    (0..POSEIDON_ROUNDS).fold(input, |prev, round| {
+        let rc = &params.round_constants[round];
+        let s: [_; SPONGE_WIDTH] = array_init(|j| sbox::<_, PlonkSpongeConstants>(prev[j]));
+        array_init(|i| {
+            let m = &params.mds[i];
+            rc[i]
+                + &s.iter()
+                    .zip(m.iter())
+                    .fold(F::zero(), |x, (s, &m)| m * s + x)
+        })
+    })
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum SpongeState {
+    Absorbed(usize),
+    Squeezed(usize),
+}
+
+// A sponge over [permute] with the same absorb/squeeze behaviour as
+// oracle::poseidon::ArithmeticSponge. The first SPONGE_RATE elements of the
+// state are the rate and the last one is the capacity.
+#[derive(Clone)]
+pub struct Sponge<F> {
+    state: [F; SPONGE_WIDTH],
+    mode: SpongeState,
+}
+
+impl<F: Field> Sponge<F> {
+    pub fn new() -> Self {
+        Sponge {
+            state: [F::zero(); SPONGE_WIDTH],
+            mode: SpongeState::Absorbed(0),
+        }
+    }
+
+    pub fn absorb(&mut self, params: &ArithmeticSpongeParams<F>, xs: &[F]) {
+        for x in xs.iter() {
+            match self.mode {
+                SpongeState::Absorbed(n) => {
+                    if n == SPONGE_RATE {
+                        self.state = permute(params, self.state);
+                        self.state[0] += x;
+                        self.mode = SpongeState::Absorbed(1);
+                    } else {
+                        self.state[n] += x;
+                        self.mode = SpongeState::Absorbed(n + 1);
+                    }
+                }
+                SpongeState::Squeezed(_) => {
+                    self.state[0] += x;
+                    self.mode = SpongeState::Absorbed(1);
+                }
+            }
+        }
+    }
+
+    pub fn squeeze(&mut self, params: &ArithmeticSpongeParams<F>) -> F {
+        match self.mode {
+            SpongeState::Squeezed(n) => {
+                if n == SPONGE_RATE {
+                    self.state = permute(params, self.state);
+                    self.mode = SpongeState::Squeezed(1);
+                    self.state[0]
+                } else {
+                    self.mode = SpongeState::Squeezed(n + 1);
+                    self.state[n]
+                }
+            }
+            SpongeState::Absorbed(_) => {
+                self.state = permute(params, self.state);
+                self.mode = SpongeState::Squeezed(1);
+                self.state[0]
+            }
+        }
+    }
+}
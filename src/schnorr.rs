@@ -1,7 +1,6 @@
-use algebra::{AffineCurve, BigInteger, PrimeField, ProjectiveCurve, UniformRand, Zero};
-use array_init::array_init;
+use algebra::{AffineCurve, BigInteger, PrimeField, ProjectiveCurve, Zero};
 use commitment_dlog::commitment::CommitmentCurve;
-use oracle::{poseidon::ArithmeticSpongeParams, poseidon_5_wires::*};
+use oracle::poseidon::ArithmeticSpongeParams;
 
 use crate::{endo, random_oracle};
 
@@ -40,11 +39,19 @@ pub trait SignatureParams {
         r: <Self::G as AffineCurve>::BaseField,
     ) -> <Self::G as AffineCurve>::ScalarField;
 
+    // Derives the signing nonce from the private key and the message, so that
+    // signing never depends on the quality of the system RNG.
+    fn nonce(
+        &self,
+        d: PrivateKey<Self::G>,
+        m: &Self::Message,
+    ) -> <Self::G as AffineCurve>::ScalarField;
+
     fn sign(&self, d: PrivateKey<Self::G>, m: Self::Message) -> Signature<Self::G> {
         let base = Self::G::prime_subgroup_generator();
         let pubkey = base.mul(d).into_affine();
         let (r, k) = {
-            let k_prime = <Self::G as AffineCurve>::ScalarField::rand(&mut rand_core::OsRng);
+            let k_prime = self.nonce(d, &m);
             let (r, ry) = base.mul(k_prime).into_affine().to_coords().unwrap();
             let k = if even(ry) { k_prime } else { -k_prime };
             (r, k)
@@ -71,7 +78,32 @@ pub struct Signer<G: CoordinateCurve> {
     pub endo: G::ScalarField,
 }
 
-const COLUMNS: usize = 5;
+// Domain separation tags for the challenge and nonce sponges, so that nonces
+// can never coincide with a challenge computed by [hash].
+const HASH_DOMAIN: u64 = u64::from_le_bytes(*b"bbaschnr");
+const NONCE_DOMAIN: u64 = u64::from_le_bytes(*b"bbanonce");
+
+// The first element absorbed by a sponge. It includes the message length so that
+// messages which only differ by trailing zeros do not collide.
+pub fn domain<F: PrimeField>(tag: u64, len: usize) -> F {
+    let two: F = (2 as u64).into();
+    F::from(tag) + &(F::from(len as u64) * &two.pow(&[64]))
+}
+
+// The domain element for the challenge of a message with [len] field elements.
+// Circuits verifying signatures absorb this as a constant.
+pub fn hash_domain<F: PrimeField>(len: usize) -> F {
+    domain(HASH_DOMAIN, len)
+}
+
+// The message signed for a curve point: its affine coordinates, with the point at
+// infinity represented as (0, 0) as in the circuits.
+pub fn point_message<G: CoordinateCurve>(p: &G) -> Vec<G::BaseField> {
+    let (x, y) = p
+        .to_coords()
+        .unwrap_or((G::BaseField::zero(), G::BaseField::zero()));
+    vec![x, y]
+}
 
 fn pack<B: BigInteger>(limbs_lsb: &[u64]) -> B {
     let mut res: B = 0.into();
@@ -88,24 +120,53 @@ where
 {
     type BaseField = G::BaseField;
     type G = G;
-    type Message = G;
-
-    fn hash(&self, _pk: PublicKey<G>, m: G, r: G::BaseField) -> G::ScalarField {
-        let (x, y) = m.to_coords().unwrap();
-        let input = [x, y, r, G::BaseField::zero(), G::BaseField::zero()];
-        let res = (0..random_oracle::POSEIDON_ROUNDS).fold(input, |prev, round| {
-            let rc = &self.sponge.round_constants[round];
-            let s: [_; COLUMNS] = array_init(|j| sbox::<_, PlonkSpongeConstants>(prev[j]));
-            array_init(|i| {
-                let m = &self.sponge.mds[i];
-                rc[i]
-                    + &s.iter()
-                        .zip(m.iter())
-                        .fold(G::BaseField::zero(), |x, (s, &m)| m * s + x)
-            })
-        });
-
-        endo::EndoScalar(G::ScalarField::from_repr(pack(res[0].into_repr().as_ref())))
+    // A message is a sequence of base field elements, e.g. the coordinates of an
+    // accumulator followed by any metadata the authority wants to bind.
+    type Message = Vec<G::BaseField>;
+
+    fn hash(&self, _pk: PublicKey<G>, m: Vec<G::BaseField>, r: G::BaseField) -> G::ScalarField {
+        let mut sponge = random_oracle::Sponge::new();
+        sponge.absorb(&self.sponge, &[hash_domain(m.len())]);
+        sponge.absorb(&self.sponge, &m);
+        sponge.absorb(&self.sponge, &[r]);
+        let e = sponge.squeeze(&self.sponge);
+
+        endo::EndoScalar(G::ScalarField::from_repr(pack(e.into_repr().as_ref())))
             .to_field(&self.endo)
     }
+
+    fn nonce(&self, d: PrivateKey<G>, m: &Vec<G::BaseField>) -> G::ScalarField {
+        let [d_lo, d_hi] = scalar_halves::<G>(d);
+
+        let mut sponge = random_oracle::Sponge::new();
+        sponge.absorb(&self.sponge, &[domain(NONCE_DOMAIN, m.len()), d_lo, d_hi]);
+        sponge.absorb(&self.sponge, m);
+        truncated_scalar::<G>(sponge.squeeze(&self.sponge))
+    }
+}
+
+// A scalar, e.g. the private key, does not fit in a single base field element in
+// general, so it is absorbed as two 128-bit halves.
+pub fn scalar_halves<G: CoordinateCurve>(x: G::ScalarField) -> [G::BaseField; 2]
+where
+    G::BaseField: PrimeField,
+{
+    let repr = x.into_repr();
+    let limbs = repr.as_ref();
+    [
+        G::BaseField::from_repr(pack(&limbs[..2])),
+        G::BaseField::from_repr(pack(&limbs[2..])),
+    ]
+}
+
+// Keeps the low 254 bits of a base field element, which is below both Pasta moduli
+// and so is always a canonical scalar.
+fn truncated_scalar<G: CoordinateCurve>(x: G::BaseField) -> G::ScalarField
+where
+    G::BaseField: PrimeField,
+{
+    let mut limbs = x.into_repr().as_ref().to_vec();
+    let n = limbs.len();
+    limbs[n - 1] &= (1 << 62) - 1;
+    G::ScalarField::from_repr(pack(&limbs))
 }
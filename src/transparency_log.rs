@@ -0,0 +1,316 @@
+use crate::random_oracle::Sponge;
+use crate::schnorr::{self, CoordinateCurve, SignatureParams};
+use algebra::{AffineCurve, PrimeField};
+use oracle::poseidon::ArithmeticSpongeParams;
+use std::collections::HashMap;
+
+// An append-only Merkle tree over the accumulators the authority signs and the
+// nullifiers it redeems. The tree has the shape of RFC 6962 with Poseidon as the
+// hash, so that users can check their signed accumulator was logged and auditors
+// can check that the log only ever grows and count what was issued.
+
+const LEAF_TAG: u64 = u64::from_le_bytes(*b"bbaleaf0");
+const NODE_TAG: u64 = u64::from_le_bytes(*b"bbanode0");
+const EMPTY_TAG: u64 = u64::from_le_bytes(*b"bbaempty");
+const TREE_HEAD_TAG: u64 = u64::from_le_bytes(*b"bbahead0");
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Entry<G: AffineCurve> {
+    // An accumulator signed in response to an init or update request
+    Accumulator(G, schnorr::Signature<G>),
+    // The nullifier of a redeemed opening
+    Redemption(G::ScalarField),
+}
+
+impl<G: CoordinateCurve> Entry<G>
+where
+    G::BaseField: PrimeField,
+{
+    fn fields(&self) -> Vec<G::BaseField> {
+        match self {
+            Entry::Accumulator(acc, (r, s)) => {
+                let mut m = schnorr::point_message(acc);
+                m.push(*r);
+                m.extend_from_slice(&schnorr::scalar_halves::<G>(*s));
+                m
+            }
+            Entry::Redemption(nullifier) => schnorr::scalar_halves::<G>(*nullifier).to_vec(),
+        }
+    }
+
+    pub fn leaf_hash(&self, params: &ArithmeticSpongeParams<G::BaseField>) -> G::BaseField {
+        // The two kinds of entries have different lengths, which the tag absorbs
+        hash(params, LEAF_TAG, &self.fields())
+    }
+}
+
+fn hash<F: PrimeField>(params: &ArithmeticSpongeParams<F>, tag: u64, xs: &[F]) -> F {
+    let mut sponge = Sponge::new();
+    sponge.absorb(params, &[schnorr::domain(tag, xs.len())]);
+    sponge.absorb(params, xs);
+    sponge.squeeze(params)
+}
+
+fn node_hash<F: PrimeField>(params: &ArithmeticSpongeParams<F>, l: F, r: F) -> F {
+    hash(params, NODE_TAG, &[l, r])
+}
+
+// The largest power of two smaller than [n], for n > 1
+fn split(n: usize) -> usize {
+    let mut k = 1;
+    while 2 * k < n {
+        k *= 2;
+    }
+    k
+}
+
+pub struct Log<G: AffineCurve> {
+    params: ArithmeticSpongeParams<G::BaseField>,
+    entries: Vec<Entry<G>>,
+    leaves: Vec<G::BaseField>,
+    indices: HashMap<G::BaseField, usize>,
+}
+
+impl<G: CoordinateCurve> Log<G>
+where
+    G::BaseField: PrimeField,
+{
+    pub fn new(params: ArithmeticSpongeParams<G::BaseField>) -> Self {
+        Log {
+            params,
+            entries: vec![],
+            leaves: vec![],
+            indices: HashMap::new(),
+        }
+    }
+
+    // Returns the index of the new entry
+    pub fn append(&mut self, entry: Entry<G>) -> usize {
+        let leaf = entry.leaf_hash(&self.params);
+        let i = self.leaves.len();
+        self.entries.push(entry);
+        self.leaves.push(leaf);
+        self.indices.entry(leaf).or_insert(i);
+        i
+    }
+
+    pub fn size(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn entries(&self) -> &[Entry<G>] {
+        &self.entries
+    }
+
+    pub fn index_of(&self, entry: &Entry<G>) -> Option<usize> {
+        self.indices.get(&entry.leaf_hash(&self.params)).copied()
+    }
+
+    // The root of the tree over the first [size] entries. This rehashes the whole
+    // tree, which is fine for the sizes this proof of concept runs at; a deployed
+    // log would cache the roots of complete subtrees.
+    pub fn root(&self, size: usize) -> G::BaseField {
+        assert!(size <= self.size());
+        if size == 0 {
+            hash(&self.params, EMPTY_TAG, &[])
+        } else {
+            self.subtree_root(&self.leaves[..size])
+        }
+    }
+
+    fn subtree_root(&self, leaves: &[G::BaseField]) -> G::BaseField {
+        if leaves.len() == 1 {
+            return leaves[0];
+        }
+        let k = split(leaves.len());
+        node_hash(
+            &self.params,
+            self.subtree_root(&leaves[..k]),
+            self.subtree_root(&leaves[k..]),
+        )
+    }
+
+    // The audit path of entry [index] in the tree over the first [size] entries
+    pub fn inclusion_proof(&self, index: usize, size: usize) -> Vec<G::BaseField> {
+        assert!(index < size && size <= self.size());
+        let mut proof = vec![];
+        self.path(index, &self.leaves[..size], &mut proof);
+        proof
+    }
+
+    fn path(&self, m: usize, leaves: &[G::BaseField], proof: &mut Vec<G::BaseField>) {
+        if leaves.len() == 1 {
+            return;
+        }
+        let k = split(leaves.len());
+        if m < k {
+            self.path(m, &leaves[..k], proof);
+            proof.push(self.subtree_root(&leaves[k..]));
+        } else {
+            self.path(m - k, &leaves[k..], proof);
+            proof.push(self.subtree_root(&leaves[..k]));
+        }
+    }
+
+    // A proof that the tree over the first [old_size] entries is a prefix of the
+    // tree over the first [new_size] entries
+    pub fn consistency_proof(&self, old_size: usize, new_size: usize) -> Vec<G::BaseField> {
+        assert!(old_size <= new_size && new_size <= self.size());
+        let mut proof = vec![];
+        if old_size > 0 {
+            self.subproof(old_size, &self.leaves[..new_size], true, &mut proof);
+        }
+        proof
+    }
+
+    fn subproof(
+        &self,
+        m: usize,
+        leaves: &[G::BaseField],
+        complete: bool,
+        proof: &mut Vec<G::BaseField>,
+    ) {
+        let n = leaves.len();
+        if m == n {
+            if !complete {
+                proof.push(self.subtree_root(leaves));
+            }
+            return;
+        }
+        let k = split(n);
+        if m <= k {
+            self.subproof(m, &leaves[..k], complete, proof);
+            proof.push(self.subtree_root(&leaves[k..]));
+        } else {
+            self.subproof(m - k, &leaves[k..], false, proof);
+            proof.push(self.subtree_root(&leaves[..k]));
+        }
+    }
+}
+
+// Checks an audit path from [Log::inclusion_proof], as in RFC 9162 section 2.1.3.2
+pub fn verify_inclusion<F: PrimeField>(
+    params: &ArithmeticSpongeParams<F>,
+    leaf: F,
+    index: usize,
+    size: usize,
+    proof: &[F],
+    root: F,
+) -> bool {
+    if index >= size {
+        return false;
+    }
+    let (mut f, mut s) = (index, size - 1);
+    let mut r = leaf;
+    for p in proof.iter() {
+        if s == 0 {
+            return false;
+        }
+        if f & 1 == 1 || f == s {
+            r = node_hash(params, *p, r);
+            while f & 1 == 0 && f != 0 {
+                f >>= 1;
+                s >>= 1;
+            }
+        } else {
+            r = node_hash(params, r, *p);
+        }
+        f >>= 1;
+        s >>= 1;
+    }
+    s == 0 && r == root
+}
+
+// Checks a proof from [Log::consistency_proof], as in RFC 9162 section 2.1.4.2
+pub fn verify_consistency<F: PrimeField>(
+    params: &ArithmeticSpongeParams<F>,
+    old_size: usize,
+    new_size: usize,
+    old_root: F,
+    new_root: F,
+    proof: &[F],
+) -> bool {
+    if old_size > new_size {
+        return false;
+    }
+    if old_size == new_size {
+        return proof.is_empty() && old_root == new_root;
+    }
+    if old_size == 0 {
+        return proof.is_empty();
+    }
+
+    let mut proof = proof.to_vec();
+    if old_size.is_power_of_two() {
+        proof.insert(0, old_root);
+    }
+    if proof.is_empty() {
+        return false;
+    }
+    let (mut f, mut s) = (old_size - 1, new_size - 1);
+    while f & 1 == 1 {
+        f >>= 1;
+        s >>= 1;
+    }
+    let (mut fr, mut sr) = (proof[0], proof[0]);
+    for c in proof[1..].iter() {
+        if s == 0 {
+            return false;
+        }
+        if f & 1 == 1 || f == s {
+            fr = node_hash(params, *c, fr);
+            sr = node_hash(params, *c, sr);
+            while f & 1 == 0 && f != 0 {
+                f >>= 1;
+                s >>= 1;
+            }
+        } else {
+            sr = node_hash(params, sr, *c);
+        }
+        f >>= 1;
+        s >>= 1;
+    }
+    fr == old_root && sr == new_root && s == 0
+}
+
+// The root of the log at some size, signed by the authority
+#[derive(Clone, Debug, PartialEq)]
+pub struct SignedTreeHead<G: AffineCurve> {
+    pub size: usize,
+    pub root: G::BaseField,
+    pub signature: schnorr::Signature<G>,
+}
+
+// Tree heads are signed with the accumulator signing key. Their messages start
+// with a tag and have a length of their own, so their signatures never verify
+// for accumulators or receipts.
+fn tree_head_message<F: PrimeField>(size: usize, root: F) -> Vec<F> {
+    vec![F::from(TREE_HEAD_TAG), F::from(size as u64), root]
+}
+
+impl<G: CoordinateCurve> SignedTreeHead<G>
+where
+    G::BaseField: PrimeField,
+{
+    pub fn sign(
+        signer: &schnorr::Signer<G>,
+        signing_key: schnorr::PrivateKey<G>,
+        log: &Log<G>,
+    ) -> Self {
+        let size = log.size();
+        let root = log.root(size);
+        SignedTreeHead {
+            size,
+            root,
+            signature: signer.sign(signing_key, tree_head_message(size, root)),
+        }
+    }
+
+    pub fn verify(&self, signer: &schnorr::Signer<G>, authority_public_key: G) -> bool {
+        signer.verify(
+            authority_public_key,
+            tree_head_message(self.size, self.root),
+            self.signature,
+        )
+    }
+}
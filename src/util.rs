@@ -1,3 +1,7 @@
+use crate::proof_system::System;
+use algebra::{FftField, PrimeField};
+use std::path::Path;
+
 pub fn time<A, F>(label: &str, f: F) -> A
 where
     F: FnOnce() -> A,
@@ -19,3 +23,40 @@ where
     println!("{} ({:?} for {} {}s, {:?} per {})", label, elapsed, batch_size, item_label, per_item, item_label);
     x
 }
+
+// Prints the per-scope row counts of a circuit and writes its gate layout to
+// [dir]/[name].csv and [dir]/[name].json
+pub fn write_circuit_stats<F: PrimeField + FftField>(dir: &str, name: &str, sys: &System<F>) {
+    println!("{} circuit rows\n{}", name, sys.row_count_report());
+    let dir = Path::new(dir);
+    std::fs::create_dir_all(dir).unwrap();
+    std::fs::write(dir.join(format!("{}.csv", name)), sys.gates_csv()).unwrap();
+    std::fs::write(dir.join(format!("{}.json", name)), sys.gates_json()).unwrap();
+}
+
+pub fn field_hex<F: PrimeField>(x: &F) -> String {
+    let repr = x.into_repr();
+    let limbs: Vec<_> = repr.as_ref().iter().rev().map(|l| format!("{:016x}", l)).collect();
+    format!("0x{}", limbs.concat())
+}
+
+// The inverse of [field_hex]. Only canonical encodings are accepted.
+pub fn field_from_hex<F: PrimeField>(s: &str) -> Option<F> {
+    let digits = s.strip_prefix("0x")?;
+    let mut repr = F::BigInt::default();
+    let limbs = repr.as_mut();
+    if digits.len() != 16 * limbs.len() {
+        return None;
+    }
+    let n = limbs.len();
+    for (i, limb) in limbs.iter_mut().enumerate() {
+        let chunk = digits.get(16 * (n - 1 - i)..16 * (n - i))?;
+        *limb = u64::from_str_radix(chunk, 16).ok()?;
+    }
+    let x = F::from_repr(repr);
+    if field_hex(&x) == s {
+        Some(x)
+    } else {
+        None
+    }
+}